@@ -1,7 +1,10 @@
+#![allow(clippy::needless_return)]
+
 use clap::{Arg, App, app_from_crate, crate_authors, crate_description,
            crate_name, crate_version, Error, value_t, Values};
 
-use turnip_calc_lib::Pattern;
+use turnip_calc_lib::{CalcError, Calculator, HALF_DAY_NAMES, Observation, Options, Pattern,
+                       PatternDistribution};
 
 // Pattern names.
 const DECREASING: &str = "decreasing";
@@ -12,12 +15,22 @@ const PATTERNS: [&str; 4] = [DECREASING, RANDOM, SMALL_SPIKE, LARGE_SPIKE];
 
 // Argument names.
 const LAST_WEEK: &str = "last_week";
+const FIRST_WEEK: &str = "FIRST_WEEK";
+const FLOAT32: &str = "FLOAT32";
 const BASE_PRICE: &str = "BASE_PRICE";
 const PRICES: &str = "PRICES";
+const ERROR_RATE: &str = "ERROR_RATE";
+const FORECAST: &str = "FORECAST";
+const PARAMETERS: &str = "PARAMETERS";
 const DEBUG: &str = "DEBUG";
 
 // Argument values.
 const MISSING_PRICE: &str = "?";
+const LOWER: &str = "lower";
+const HIGHER: &str = "higher";
+
+// Chances below this round to 0% and are not worth listing in the forecast.
+const NEGLIGIBLE: f64 = 0.005;
 
 fn cli() -> App<'static, 'static> {
     // Hack to make the build dirty when the toml changes.
@@ -28,34 +41,68 @@ fn cli() -> App<'static, 'static> {
         observed turnip prices. Specifying last week's pattern will increase the \
         accuracy of results, since the previous pattern affects the chance of \
         the next one.\n\n\
-        In theory, this tool is 100% accurate. It should always list precisely \
-        the remaining possible patterns, with the most accurate estimate of \
-        probability that one can theoretically calculate. This assumes that \
-        the reverse-engineered turnip code from New Horizons is accurate, as \
-        this tool mirrors its calculations.\n\n\
-        Example usage: turnip-calc 90 --last-week smallspike 55 52 ? 43")
+        In theory, this tool is almost 100% accurate. It should list the \
+        remaining possible patterns, with very nearly the most accurate \
+        estimate of probability that one can theoretically calculate. It only \
+        makes a few small approximations, e.g. to the game's rounding unless \
+        --float32 is given, which are described in the README. This assumes \
+        that the reverse-engineered turnip code from New Horizons is accurate, \
+        as this tool mirrors its calculations.\n\n\
+        Example usage: turnip-calc 90 --last-week smallspike 55 52 ? 40-45")
         .max_term_width(80)
         .arg(Arg::with_name(LAST_WEEK)
-            .help("Last week's pattern.")
+            .help("Last week's pattern. If unsure, give the chance of each \
+                   likely pattern instead, e.g. 'largespike=0.7,smallspike=0.3'. \
+                   [possible patterns: decreasing, random, smallspike, largespike]")
             .short("l")
             .long("last-week")
-            .takes_value(true)
-            .possible_values(&PATTERNS)
-            .case_insensitive(true))
+            .takes_value(true))
+        .arg(Arg::with_name(FIRST_WEEK)
+            .help("This is the first week of buying turnips on your island, \
+                   which always follows the Small Spike pattern.")
+            .long("first-week")
+            .takes_value(false)
+            .conflicts_with(LAST_WEEK))
+        .arg(Arg::with_name(FLOAT32)
+            .help("Reproduce the game's 32-bit floating point arithmetic exactly, \
+                   for prices right at the edge of what a pattern allows.")
+            .long("float32")
+            .takes_value(false))
         .arg(Arg::with_name(BASE_PRICE)
-            .help("The price you bought turnips for.")
+            .help("The price you bought turnips for, or '?' if unknown.")
             .takes_value(true)
             .required(true))
         .arg(Arg::with_name(PRICES)
             .help("The sell prices observed so far in order. \
-                   Missed prices can be replaced with '?'.")
+                   Missed prices can be replaced with '?'. \
+                   Vague prices can be given as a range ('80-90'), a bound \
+                   ('150+' or '80-'), or compared to the previous price \
+                   ('lower' or 'higher').")
             .takes_value(true)
             .required(false)
             .multiple(true)
             .min_values(0)
             .max_values(12))
+        .arg(Arg::with_name(ERROR_RATE)
+            .help("The chance that any given price was entered wrongly, e.g. 0.01. \
+                   If set, a mistyped price makes patterns less likely instead \
+                   of ruling them out.")
+            .short("e")
+            .long("error-rate")
+            .takes_value(true))
+        .arg(Arg::with_name(FORECAST)
+            .help("Also show the current phase and forecasts for the rest of the week.")
+            .short("f")
+            .long("forecast")
+            .takes_value(false))
+        .arg(Arg::with_name(PARAMETERS)
+            .help("Also show what the prices reveal about the hidden values the game \
+                   chose: the length of each phase and the rates behind the prices.")
+            .short("p")
+            .long("parameters")
+            .takes_value(false))
         .arg(Arg::with_name(DEBUG)
-            .help("Enable debug dumps.")
+            .help("Enable debug dumps of the pattern trees.")
             .short("d")
             .long("debug")
             .takes_value(false))
@@ -63,53 +110,262 @@ fn cli() -> App<'static, 'static> {
 
 fn main() {
     let args = cli().get_matches();
-    let last_week = args.value_of(LAST_WEEK).map(|val| {
-        match val.to_ascii_lowercase().as_str() {
-            DECREASING => Pattern::Decreasing,
-            RANDOM => Pattern::Random,
-            SMALL_SPIKE => Pattern::SmallSpike,
-            LARGE_SPIKE => Pattern::LargeSpike,
-            _ => unreachable!(),
-        }
-    });
-    let base_price = value_t!(args, BASE_PRICE, u32).unwrap_or_else(|e| e.exit());
+    let last_week = match args.value_of(LAST_WEEK) {
+        Some(arg) => parse_last_week(arg),
+        None => PatternDistribution::default(),
+    };
+    let base_price = parse_price(args.value_of(BASE_PRICE).unwrap());
     let prices = match args.values_of(PRICES) {
         Some(args) => parse_prices(args),
         None => Vec::new(),
     };
+    let options = Options {
+        error_rate: if args.is_present(ERROR_RATE) {
+            value_t!(args, ERROR_RATE, f64).unwrap_or_else(|e| e.exit())
+        } else {
+            0.0
+        },
+        first_week: args.is_present(FIRST_WEEK),
+        float32: args.is_present(FLOAT32),
+        ..Options::default()
+    };
+    let show_forecast = args.is_present(FORECAST);
+    let show_parameters = args.is_present(PARAMETERS);
     let debug = args.is_present(DEBUG);
 
-    let results = turnip_calc_lib::run(last_week, base_price, prices, debug);
-    if results.is_empty() {
-        println!("These prices did not match any known pattern. Either your \
-                  numbers are wrong, or there is a bug.");
-        return;
+    let mut calculator = or_exit(Calculator::new(last_week, base_price, options));
+    calculator.set_debug(debug);
+    or_exit(calculator.extend(prices));
+
+    let results = calculator.analysis();
+    if let Err(CalcError::NoMatchingPattern) = results {
+        // Show where each pattern went wrong, to help find the mistake.
+        print_exclusions(&calculator);
+        print_suspects(&calculator);
+        println!();
     }
+    let results = or_exit(results);
 
     println!("Analysis:");
-    for (pattern, chance) in results.iter() {
-        println!("{:?}: {:.0}%", pattern, chance * 100.0);
+    for (pattern, chance) in results.ranked() {
+        if chance >= NEGLIGIBLE {
+            println!("{:?}: {:.0}%", pattern, chance * 100.0);
+        } else if chance > 0.0 {
+            println!("{:?}: <1%", pattern);
+        }
+    }
+    if base_price.is_none() {
+        let base_prices: Vec<String> = results.base_prices().iter()
+            .filter(|(_, chance)| *chance >= NEGLIGIBLE)
+            .map(|(price, chance)| format!("{} {:.0}%", price, chance * 100.0))
+            .collect();
+        println!("\nBase price: {}", base_prices.join(", "));
+    }
+    print_exclusions(&calculator);
+
+    if show_forecast {
+        print_forecast(&calculator);
+    }
+    if show_parameters {
+        print_parameters(&calculator);
     }
 }
 
-fn parse_prices(args: Values) -> Vec<Option<u32>> {
-    let mut prices = Vec::with_capacity(args.len());
-    for arg in args {
-        if arg == MISSING_PRICE {
-            prices.push(None);
-        } else {
-            let price = match arg.parse::<u32>() {
-                Ok(p) => p,
-                _ => {
-                    let msg = format!(
-                        "The argument '{}' should be a non-negative integer or \
-                         the character '?'", arg);
-                    let err = Error::value_validation_auto(msg);
-                    err.exit();
-                }
-            };
-            prices.push(Some(price));
+fn print_exclusions(calculator: &Calculator) {
+    let exclusions = calculator.explain();
+    if !exclusions.is_empty() {
+        println!("\nRuled out:");
+        for exclusion in exclusions {
+            println!("{}", exclusion);
+        }
+    }
+}
+
+fn print_suspects(calculator: &Calculator) {
+    let prices = calculator.observations();
+    let suspects = match calculator.diagnose() {
+        Ok(suspects) => suspects,
+        Err(_) => return,
+    };
+
+    println!("\nPrices most likely to be typos:");
+    for suspect in suspects {
+        let half_days: Vec<String> = suspect.half_days.iter()
+            .map(|i| format!("{} ({})", HALF_DAY_NAMES[*i], prices[*i]))
+            .collect();
+        let patterns: Vec<String> = suspect.analysis.ranked().iter()
+            .filter(|(_, chance)| *chance > 0.0)
+            .map(|(pattern, chance)| format!("{:?} {:.0}%", pattern, chance * 100.0))
+            .collect();
+        println!("{}: would leave {}", half_days.join(" and "), patterns.join(", "));
+    }
+}
+
+fn print_forecast(calculator: &Calculator) {
+    let phases = or_exit(calculator.current_phase());
+    if !phases.is_empty() {
+        println!("\nCurrent phase:");
+        for state in phases.iter().filter(|s| s.probability >= NEGLIGIBLE) {
+            println!("{:?} {} #{}: {:.0}%", state.pattern, state.phase,
+                     state.position, state.probability * 100.0);
+        }
+    }
+
+    println!("\nForecast:");
+    let distributions = or_exit(calculator.distribution());
+    for day in or_exit(calculator.forecast()) {
+        let patterns: Vec<String> = day.patterns.iter()
+            .map(|(pattern, band)| format!("{:?} {}-{}", pattern, band.min, band.max))
+            .collect();
+        let expected = distributions[day.half_day].expected();
+        println!("{}: {}-{}, expected {:.0} ({})", HALF_DAY_NAMES[day.half_day],
+                 day.overall.min, day.overall.max, expected, patterns.join(", "));
+    }
+
+    let max_price = or_exit(calculator.max_price());
+    if !max_price.probabilities.is_empty() {
+        println!();
+        if let Some(base_price) = calculator.base_price() {
+            println!("Chance to make a profit: {:.0}%", max_price.at_least(base_price + 1) * 100.0);
+            println!("Chance to double your money: {:.0}%", max_price.at_least(base_price * 2) * 100.0);
+        }
+        println!("Expected best price: {:.0}", max_price.expected());
+    }
+
+    let timings = or_exit(calculator.spike_timing());
+    for timing in timings.iter().filter(|t| t.probability >= NEGLIGIBLE) {
+        let peak = timing.likeliest_peak();
+        println!("{:?} peak most likely on {} ({:.0}%)", timing.pattern,
+                 HALF_DAY_NAMES[peak], timing.peak[peak] * 100.0);
+    }
+
+    let next_week: Vec<String> = or_exit(calculator.next_week()).ranked().iter()
+        .map(|(pattern, chance)| format!("{:?} {:.0}%", pattern, chance * 100.0))
+        .collect();
+    println!("\nNext week: {}", next_week.join(", "));
+}
+
+fn print_parameters(calculator: &Calculator) {
+    println!("\nHidden parameters:");
+    for params in or_exit(calculator.hidden_parameters()) {
+        if params.probability < NEGLIGIBLE {
+            continue;
+        }
+        println!("{:?} ({:.0}%):", params.pattern, params.probability * 100.0);
+        for phase in params.phases {
+            let lengths: Vec<String> = phase.lengths.iter()
+                .filter(|(_, chance)| *chance >= NEGLIGIBLE)
+                .map(|(length, chance)| format!("{} {:.0}%", length, chance * 100.0))
+                .collect();
+            print!("  {}: length {}", phase.phase, lengths.join(", "));
+            if let Some(rate) = phase.initial_rate {
+                print!(", starting at {:.3}-{:.3}", rate.min(), rate.max());
+            }
+            println!();
+        }
+        if let Some(rate) = params.peak_rate {
+            println!("  Peak: {:.3}-{:.3}, expected {:.3}", rate.min(), rate.max(), rate.expected());
+        }
+    }
+}
+
+/// Unwrap the result of the calculator, or print the error and exit.
+fn or_exit<T>(result: Result<T, CalcError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_prices(args: Values) -> Vec<Observation> {
+    return args.map(parse_observation).collect();
+}
+
+fn parse_observation(arg: &str) -> Observation {
+    let parse = |num: &str| num.parse::<u32>().ok();
+    let observation = match arg.to_ascii_lowercase().as_str() {
+        MISSING_PRICE => Some(Observation::Unknown),
+        LOWER => Some(Observation::LowerThanPrevious),
+        HIGHER => Some(Observation::HigherThanPrevious),
+        arg => {
+            if let Some(min) = arg.strip_suffix('+') {
+                parse(min).map(Observation::AtLeast)
+            } else if let Some(max) = arg.strip_suffix('-') {
+                parse(max).map(Observation::AtMost)
+            } else if let Some((min, max)) = arg.split_once('-') {
+                parse(min).zip(parse(max)).map(|(min, max)| Observation::Between(min, max))
+            } else {
+                parse(arg).map(Observation::Exact)
+            }
+        }
+    };
+
+    match observation {
+        Some(observation) => observation,
+        None => {
+            let msg = format!(
+                "The argument '{}' should be a non-negative integer, a range such \
+                 as '80-90', a bound such as '150+' or '80-', 'lower', 'higher', \
+                 or the character '?'", arg);
+            let err = Error::value_validation_auto(msg);
+            err.exit();
+        }
+    }
+}
+
+fn parse_last_week(arg: &str) -> PatternDistribution {
+    let parse_pattern = |name: &str| match name.trim().to_ascii_lowercase().as_str() {
+        DECREASING => Some(Pattern::Decreasing),
+        RANDOM => Some(Pattern::Random),
+        SMALL_SPIKE => Some(Pattern::SmallSpike),
+        LARGE_SPIKE => Some(Pattern::LargeSpike),
+        _ => None,
+    };
+
+    // Either a single pattern, or a list of patterns with their chances.
+    let probabilities = if arg.contains('=') {
+        arg.split(',')
+            .map(|entry| {
+                let (name, prob) = entry.split_once('=')?;
+                Some((parse_pattern(name)?, prob.trim().parse::<f64>().ok()?))
+            })
+            .collect::<Option<Vec<(Pattern, f64)>>>()
+    } else {
+        parse_pattern(arg).map(|pattern| vec![(pattern, 1.0)])
+    };
+
+    match probabilities.map(|p| PatternDistribution::new(&p)) {
+        Some(Ok(distribution)) => distribution,
+        Some(Err(e)) => {
+            let err = Error::value_validation_auto(e.to_string());
+            err.exit();
+        }
+        None => {
+            let msg = format!(
+                "The argument '{}' should be one of [{}], or a list of them with \
+                 their chances such as 'largespike=0.7,smallspike=0.3'",
+                arg, PATTERNS.join(", "));
+            let err = Error::value_validation_auto(msg);
+            err.exit();
+        }
+    }
+}
+
+fn parse_price(arg: &str) -> Option<u32> {
+    if arg == MISSING_PRICE {
+        return None;
+    }
+    match arg.parse::<u32>() {
+        Ok(p) => Some(p),
+        _ => {
+            let msg = format!(
+                "The argument '{}' should be a non-negative integer or \
+                 the character '?'", arg);
+            let err = Error::value_validation_auto(msg);
+            err.exit();
         }
     }
-    return prices;
 }
@@ -43,6 +43,13 @@ public class TurnipCalc {
     public static final byte SMALL_SPIKE = 3;
     public static final byte LARGE_SPIKE = 4;
 
+    public static final byte ERROR_NONE = 0;
+    public static final byte ERROR_INVALID_BASE_PRICE = 1;
+    public static final byte ERROR_TOO_MANY_PRICES = 2;
+    public static final byte ERROR_NO_MATCHING_PATTERN = 3;
+    public static final byte ERROR_INTERNAL = 4;
+    public static final byte ERROR_INVALID_OPTIONS = 5;
+
     public static class PatternResult {
         public final byte pattern;
         public final double probability;
@@ -55,19 +62,26 @@ public class TurnipCalc {
 
     public static class CalcResult {
         public final boolean success;
+        public final byte error;
         public final PatternResult[] results;
 
-        public CalcResult(final boolean success, final PatternResult[] results) {
+        public CalcResult(final boolean success, final byte error, final PatternResult[] results) {
             this.success = success;
+            this.error = error;
             this.results = results;
         }
     }
 
-    public static native CalcResult run(byte prev_pattern, int base_price, int[] prices);
+    public static CalcResult run(byte prev_pattern, int base_price, int[] prices) {
+        return runWithError(prev_pattern, base_price, prices, 0.0);
+    }
+
+    public static native CalcResult runWithError(byte prev_pattern, int base_price, int[] prices, double error_rate);
 
     public static void main(final String[] args) {
         final CalcResult result = run(SMALL_SPIKE, 95, new int[]{85, 81});
         System.out.println("Success: " + result.success);
+        System.out.println("Error: " + result.error);
         for (final PatternResult pattern : result.results) {
             System.out.print(pattern.pattern);
             System.out.println(" " + pattern.probability);
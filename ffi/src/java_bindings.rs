@@ -1,5 +1,5 @@
 use jni::JNIEnv;
-use jni::objects::{JClass, JValue, ReleaseMode};
+use jni::objects::{JClass, JObject, JValue, ReleaseMode};
 use jni::sys::{jboolean, jbyte, jdouble, jint, jintArray, jobject, jsize};
 use std::ptr;
 
@@ -11,6 +11,7 @@ const CALC_RESULT: &str = "com/cjriches/turnipcalc/TurnipCalc$CalcResult";
 // Constructor signatures
 const PATTERN_RESULT_CTOR: &str = "(BD)V";
 const CALC_RESULT_CTOR: &str = "(Z[Lcom/cjriches/turnipcalc/TurnipCalc$PatternResult;)V";
+const CALC_RESULT_ERROR_CTOR: &str = "(ZB[Lcom/cjriches/turnipcalc/TurnipCalc$PatternResult;)V";
 
 /// This can be referenced in Java code by a:
 /// * method called `run`
@@ -19,7 +20,11 @@ const CALC_RESULT_CTOR: &str = "(Z[Lcom/cjriches/turnipcalc/TurnipCalc$PatternRe
 ///
 /// Such a class is provided at [../com/cjriches/turnipcalc/TurnipCalc.java].
 /// Just remove the main function and plug it into your Java application.
-/// ```
+/// The class provided now implements `run` with `runWithError` instead, so
+/// that it can report errors; this is kept for older copies of the class.
+///
+/// # Safety
+/// Must only be called by the JVM, with a valid `prices` array.
 #[no_mangle]
 pub unsafe extern "system" fn Java_com_cjriches_turnipcalc_TurnipCalc_run(
     env: JNIEnv, _: JClass, prev_pattern: jbyte, base_price: jint, prices: jintArray)
@@ -38,11 +43,65 @@ pub unsafe extern "system" fn Java_com_cjriches_turnipcalc_TurnipCalc_run(
     let calc_result = turnip_calc(prev_pattern_conv, base_price_conv, prices_conv, num_prices);
 
     // Construct return value.
+    let success = JValue::Bool(calc_result.success as jboolean);
+    let results = JValue::Object(pattern_results(&env, &calc_result));
+    let calc_result_java = env.new_object(CALC_RESULT, CALC_RESULT_CTOR, &[success, results])
+        .expect("Failed to create CalcResult");
+
+    // Free memory.
+    free_result(calc_result);
+
+    return calc_result_java.into_inner();
+}
+
+/// The same as `run`, but allowing for mistyped prices and reporting the reason
+/// for any failure. This can be referenced by a method called `runWithError`
+/// in the same class.
+///
+/// # Safety
+/// Must only be called by the JVM, with a valid `prices` array.
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_cjriches_turnipcalc_TurnipCalc_runWithError(
+    env: JNIEnv, _: JClass, prev_pattern: jbyte, base_price: jint, prices: jintArray,
+    error_rate: jdouble) -> jobject
+{
+    // Convert arguments.
+    let prev_pattern_conv = prev_pattern as u8;
+    let base_price_conv = base_price as u32;
+    let prices_arr = env.get_int_array_elements(prices, ReleaseMode::NoCopyBack)
+        .expect("Failed to read prices array");  // Must keep a reference or the array gets freed underneath us.
+    let prices_conv = prices_arr.as_ptr() as *const u32;
+    let num_prices = env.get_array_length(prices)
+        .expect("Failed to read prices array length") as usize;
+
+    // Run implementation.
+    let mut error_code = ErrorCode::None;
+    let calc_result = turnip_calc_with_error(prev_pattern_conv, base_price_conv, prices_conv,
+                                             num_prices, error_rate, &mut error_code);
+
+    // Construct return value.
+    let success = JValue::Bool(calc_result.success as jboolean);
+    let error = JValue::Byte(error_code as jbyte);
+    let results = JValue::Object(pattern_results(&env, &calc_result));
+    let calc_result_java = env.new_object(CALC_RESULT, CALC_RESULT_ERROR_CTOR, &[success, error, results])
+        .expect("Failed to create CalcResult");
+
+    // Free memory.
+    free_result(calc_result);
+
+    return calc_result_java.into_inner();
+}
+
+/// Copy the pattern results out of a `CalcResult` into a Java array.
+///
+/// # Safety
+/// `calc_result` must have come from `turnip_calc` or `turnip_calc_with_error`.
+unsafe fn pattern_results<'a>(env: &JNIEnv<'a>, calc_result: &CalcResult) -> JObject<'a> {
     let pattern_results = env.new_object_array(calc_result.num as jsize,
                                                PATTERN_RESULT, ptr::null_mut())
         .expect("Failed to create result array");
     for i in 0..calc_result.num {
-        let raw_pr = ptr::read(calc_result.results.offset(i as isize));
+        let raw_pr = ptr::read(calc_result.results.add(i));
         let pattern = JValue::Byte(raw_pr.pattern as jbyte);
         let probability = JValue::Double(raw_pr.probability as jdouble);
         let pattern_result = env.new_object(PATTERN_RESULT, PATTERN_RESULT_CTOR, &[pattern, probability])
@@ -50,13 +109,5 @@ pub unsafe extern "system" fn Java_com_cjriches_turnipcalc_TurnipCalc_run(
         env.set_object_array_element(pattern_results, i as jsize, pattern_result)
             .expect("Failed to insert into result array");
     }
-    let success = JValue::Bool(calc_result.success as jboolean);
-    let results = JValue::Object(pattern_results.into());
-    let calc_result_java = env.new_object(CALC_RESULT, CALC_RESULT_CTOR, &[success, results])
-        .expect("Failed to create CalcResult");
-
-    // Free memory.
-    free_result(calc_result);
-
-    return calc_result_java.into_inner();
+    return pattern_results.into();
 }
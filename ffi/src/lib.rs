@@ -1,3 +1,5 @@
+#![allow(clippy::needless_return)]
+
 #[cfg(feature = "java-bindings")]
 pub mod java_bindings;
 
@@ -39,9 +41,9 @@ impl From<turnip_calc_lib::Pattern> for Pattern {
     }
 }
 
-impl Into<turnip_calc_lib::Pattern> for Pattern {
-    fn into(self) -> turnip_calc_lib::Pattern {
-        match self {
+impl From<Pattern> for turnip_calc_lib::Pattern {
+    fn from(p: Pattern) -> Self {
+        match p {
             Pattern::Decreasing => turnip_calc_lib::Pattern::Decreasing,
             Pattern::Random => turnip_calc_lib::Pattern::Random,
             Pattern::SmallSpike => turnip_calc_lib::Pattern::SmallSpike,
@@ -66,6 +68,33 @@ impl From<(turnip_calc_lib::Pattern, f64)> for PatternResult {
     }
 }
 
+/// C-compatible error representation.
+#[repr(u8)]
+#[derive(Copy, Clone)]
+pub enum ErrorCode {
+    None = 0,
+    InvalidBasePrice = 1,
+    TooManyPrices = 2,
+    NoMatchingPattern = 3,
+    Internal = 4,
+    InvalidOptions = 5,
+}
+
+impl From<turnip_calc_lib::CalcError> for ErrorCode {
+    fn from(e: turnip_calc_lib::CalcError) -> Self {
+        match e {
+            turnip_calc_lib::CalcError::InvalidBasePrice(_) => ErrorCode::InvalidBasePrice,
+            turnip_calc_lib::CalcError::TooManyPrices(_) => ErrorCode::TooManyPrices,
+            turnip_calc_lib::CalcError::InvalidOptions(_) => ErrorCode::InvalidOptions,
+            turnip_calc_lib::CalcError::NoMatchingPattern => ErrorCode::NoMatchingPattern,
+            // Distributions and sessions aren't available through this interface.
+            turnip_calc_lib::CalcError::InvalidDistribution(_) => ErrorCode::Internal,
+            turnip_calc_lib::CalcError::UnsupportedVersion(_) => ErrorCode::Internal,
+            turnip_calc_lib::CalcError::Inconsistent(_) => ErrorCode::Internal,
+        }
+    }
+}
+
 /// C-compatible representation of the result of running the calculator.
 #[repr(C)]
 pub struct CalcResult {
@@ -79,68 +108,114 @@ pub struct CalcResult {
 /// To free it, you must call `free_result` later.
 ///
 /// If `prev_pattern` is not set to a valid value, it will be treated as unknown.
-/// If any price is zero, it will be treated as missing.
+/// If `base_price` or any price is zero, it will be treated as unknown.
+/// Every price is trusted completely; see `turnip_calc_with_error` otherwise.
+///
+/// # Safety
+/// `prices` must point to at least `num_prices` readable `u32`s.
 #[no_mangle]
-pub unsafe extern fn turnip_calc(prev_pattern: u8, base_price: u32,
-                                 prices: *const u32, num_prices: usize) -> CalcResult {
+pub unsafe extern "C" fn turnip_calc(prev_pattern: u8, base_price: u32,
+                                     prices: *const u32, num_prices: usize) -> CalcResult {
+    return turnip_calc_with_error(prev_pattern, base_price, prices, num_prices, 0.0,
+                                  std::ptr::null_mut());
+}
+
+/// Run the turnip calculator, allowing for mistyped prices.
+/// This is the same as `turnip_calc`, except that `error_rate` is the chance
+/// that any given price was entered wrongly.
+/// If `error` is not null, the reason for any failure is written to it, or
+/// `ErrorCode::None` on success.
+///
+/// # Safety
+/// `prices` must point to at least `num_prices` readable `u32`s, and `error`
+/// must be null or point to a writable `ErrorCode`.
+#[no_mangle]
+pub unsafe extern "C" fn turnip_calc_with_error(prev_pattern: u8, base_price: u32,
+                                                prices: *const u32, num_prices: usize,
+                                                error_rate: f64, error: *mut ErrorCode) -> CalcResult {
     // Convert prev_pattern.
     let prev_pattern: Option<Pattern> = prev_pattern.try_into().ok();
 
+    // Convert base_price.
+    let base_price = if base_price == 0 {
+        None
+    } else {
+        Some(base_price)
+    };
+
     // Convert prices.
     let mut prices_vec = Vec::with_capacity(num_prices);
     for i in 0..num_prices {
-        let price = *prices.offset(i as isize);
+        let price = *prices.add(i);
         prices_vec.push(if price == 0 {
-            None
+            turnip_calc_lib::Observation::Unknown
         } else {
-            Some(price)
+            turnip_calc_lib::Observation::Exact(price)
         });
     }
 
-    // Run calculator, catching any naughty panics.
+    let options = turnip_calc_lib::Options { error_rate, ..Default::default() };
+
+    // Run calculator and convert the data, catching any naughty panics.
     let results = std::panic::catch_unwind(move || {
         turnip_calc_lib::run(prev_pattern.map(Into::into), base_price,
-                             prices_vec, false)
+                             prices_vec, &options, false)
+            .map(|results| {
+                // Leave out impossible patterns.
+                let mut results_converted = Vec::with_capacity(4);
+                for result in results.ranked() {
+                    if result.1 > 0.0 {
+                        results_converted.push(PatternResult::from(result));
+                    }
+                }
+                // Boxing guarantees the capacity matches the length.
+                results_converted.into_boxed_slice()
+            })
     });
 
     // Assemble the result.
+    let report = |code| {
+        if !error.is_null() {
+            *error = code;
+        }
+    };
     let fail_result = CalcResult {
         success: false,
         results: std::ptr::null_mut(),
         num: 0,
     };
     return match results {
-        Ok(results) => {
-            if results.is_empty() {
-                fail_result
-            } else {
-                // Convert the data.
-                let mut results_converted = Vec::with_capacity(results.len());
-                for result in results {
-                    results_converted.push(PatternResult::from(result));
-                }
-                // Release ownership.
-                results_converted.shrink_to_fit();  // Should be a no-op, but a good sanity check.
-                let vec_ptr = results_converted.as_mut_ptr();
-                let num = results_converted.len();
-                std::mem::forget(results_converted);
-                // Construct the result.
-                CalcResult {
-                    success: true,
-                    results: vec_ptr,
-                    num,
-                }
+        Ok(Ok(mut results_converted)) => {
+            report(ErrorCode::None);
+            // Release ownership.
+            let vec_ptr = results_converted.as_mut_ptr();
+            let num = results_converted.len();
+            std::mem::forget(results_converted);
+            // Construct the result.
+            CalcResult {
+                success: true,
+                results: vec_ptr,
+                num,
             }
         }
+        Ok(Err(e)) => {
+            report(e.into());
+            fail_result
+        }
         Err(_) => {
+            report(ErrorCode::Internal);
             fail_result
         }
     }
 }
 
-/// Free a `CalcResult` previously returned from `turnip_calc`.
+/// Free a `CalcResult` previously returned from `turnip_calc` or
+/// `turnip_calc_with_error`.
+///
+/// # Safety
+/// `result` must have come from one of those, and must not be freed twice.
 #[no_mangle]
-pub unsafe extern fn free_result(result: CalcResult) {
+pub unsafe extern "C" fn free_result(result: CalcResult) {
     if result.num > 0 {
         let vec = Vec::from_raw_parts(result.results, result.num, result.num);
         std::mem::drop(vec);
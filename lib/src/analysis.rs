@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+
+use crate::error::CalcError;
+use crate::node::Node;
+use crate::pattern::Pattern;
+
+/// The result of running the calculator.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Analysis {
+    /// The probability of each pattern, in the order of `Pattern::ALL`.
+    probabilities: [f64; 4],
+    /// The probability of each possible base price, in ascending order of price.
+    base_prices: Vec<(u32, f64)>,
+    /// The unnormalised probability of the observed prices, i.e. `P(prices)`.
+    evidence: f64,
+    /// The number of paths through the pattern trees that are still possible,
+    /// including those that were merged because they only differ in which
+    /// prices were taken as mistyped.
+    paths: usize,
+}
+
+impl Analysis {
+    /// Aggregate the given nodes into an analysis. If none of them has any
+    /// chance left, nothing matches.
+    pub(crate) fn new(nodes: &[Node]) -> Result<Self, CalcError> {
+        let mut probabilities = [0.0; 4];
+        let mut base_prices = BTreeMap::new();
+        for node in nodes {
+            let (pattern, prob) = node.value();
+            probabilities[pattern.index()] += prob;
+            *base_prices.entry(node.base_price()).or_insert(0.0) += prob;
+        }
+
+        // Normalise the distributions.
+        let evidence: f64 = probabilities.iter().sum();
+        if evidence <= 0.0 {
+            return Err(CalcError::NoMatchingPattern);
+        }
+        for prob in probabilities.iter_mut() {
+            *prob /= evidence;
+        }
+
+        return Ok(Analysis {
+            probabilities,
+            base_prices: base_prices.into_iter()
+                .map(|(price, prob)| (price, prob / evidence))
+                .collect(),
+            evidence,
+            paths: nodes.iter().map(Node::path_count).sum(),
+        });
+    }
+
+    /// The probability of the given pattern.
+    pub fn probability(&self, pattern: Pattern) -> f64 {
+        self.probabilities[pattern.index()]
+    }
+
+    /// The probability of every pattern, in the order of `Pattern::ALL`.
+    pub fn probabilities(&self) -> [(Pattern, f64); 4] {
+        let mut results = [(Pattern::Decreasing, 0.0); 4];
+        for (result, pattern) in results.iter_mut().zip(Pattern::ALL) {
+            *result = (pattern, self.probability(pattern));
+        }
+        return results;
+    }
+
+    /// The probability of every pattern, sorted in descending order of
+    /// likelihood. Ties are kept in the order of `Pattern::ALL`.
+    pub fn ranked(&self) -> [(Pattern, f64); 4] {
+        let mut results = self.probabilities();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1));
+        return results;
+    }
+
+    /// The probability of each base price that is still possible, in ascending
+    /// order of price. If the base price was given, this is just that price.
+    pub fn base_prices(&self) -> &[(u32, f64)] {
+        &self.base_prices
+    }
+
+    /// The probability of the given base price.
+    pub fn base_price_probability(&self, base_price: u32) -> f64 {
+        self.base_prices.iter()
+            .find(|(price, _)| *price == base_price)
+            .map_or(0.0, |(_, prob)| *prob)
+    }
+
+    /// The most likely pattern.
+    pub fn likeliest(&self) -> Pattern {
+        self.ranked()[0].0
+    }
+
+    /// The unnormalised probability of the observed prices, across all patterns.
+    /// This is the denominator `P(B)` in Bayes' theorem.
+    pub fn evidence(&self) -> f64 {
+        self.evidence
+    }
+
+    /// The number of paths through the pattern trees that are still possible.
+    pub fn paths(&self) -> usize {
+        self.paths
+    }
+}
@@ -0,0 +1,245 @@
+use std::cell::OnceCell;
+use std::collections::hash_map::{Entry, HashMap};
+
+use crate::analysis::Analysis;
+use crate::error::CalcError;
+use crate::node::{Arithmetic, MAX_HALF_DAYS, Node, State};
+use crate::observation::{Observation, PriceBand};
+use crate::options::Options;
+use crate::pattern::PatternDistribution;
+
+/// A calculator for a single week, which is given one price at a time.
+/// The pattern trees are kept after every price, so adding, undoing, or
+/// replacing the latest price only needs to traverse a single half-day.
+#[derive(Clone)]
+pub struct Calculator {
+    /// How likely each pattern was last week.
+    last_week: PatternDistribution,
+    /// The base price, if known.
+    base_price: Option<u32>,
+    /// Settings for interpreting the prices.
+    options: Options,
+    /// The prices observed so far, in order.
+    observations: Vec<Observation>,
+    /// The nodes that were possible before each price, and after the latest.
+    /// There is always one more of these than there are observations.
+    frontiers: Vec<Vec<Node>>,
+    /// The final nodes of the week given the prices so far, once they have been
+    /// needed, since every query over the rest of the week starts from them.
+    completed: OnceCell<Vec<Node>>,
+    /// Whether to dump the pattern trees after each price.
+    debug: bool,
+}
+
+impl Calculator {
+    /// Start a new week with no prices.
+    /// Last week's pattern can be given if known, or as a `PatternDistribution` if not.
+    pub fn new(last_week: impl Into<PatternDistribution>, base_price: Option<u32>,
+               options: Options) -> Result<Self, CalcError> {
+        let last_week = last_week.into();
+        options.validate()?;
+        let priors = options.priors(&last_week)?;
+        let nodes = Node::new_set(base_price, &priors, Arithmetic::new(options.float32))?;
+        return Ok(Calculator {
+            last_week,
+            base_price,
+            options,
+            observations: Vec::new(),
+            frontiers: vec![nodes],
+            completed: OnceCell::new(),
+            debug: false,
+        });
+    }
+
+    /// Start a new week with the given prices.
+    pub fn with_prices(last_week: impl Into<PatternDistribution>, base_price: Option<u32>,
+                       prices: Vec<Observation>, options: Options) -> Result<Self, CalcError> {
+        let mut calculator = Calculator::new(last_week, base_price, options)?;
+        calculator.extend(prices)?;
+        return Ok(calculator);
+    }
+
+    /// How likely each pattern was last week.
+    pub fn last_week(&self) -> &PatternDistribution {
+        &self.last_week
+    }
+
+    /// The base price, if known.
+    pub fn base_price(&self) -> Option<u32> {
+        self.base_price
+    }
+
+    /// The settings for interpreting the prices.
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// The prices observed so far, in order.
+    pub fn observations(&self) -> &[Observation] {
+        &self.observations
+    }
+
+    /// Enable or disable debug dumps of the pattern trees after each price.
+    /// When enabled, the current pattern trees are dumped straight away.
+    pub fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
+        if debug {
+            println!("\n\nINITIAL:\n{:#?}", self.frontier());
+        }
+    }
+
+    /// Add the price for the next half-day.
+    /// This succeeds even if the price doesn't match any pattern, so that it can
+    /// be undone; the queries will then fail with `CalcError::NoMatchingPattern`.
+    pub fn push(&mut self, observation: Observation) -> Result<(), CalcError> {
+        if self.observations.len() >= MAX_HALF_DAYS as usize {
+            return Err(CalcError::TooManyPrices(self.observations.len() + 1));
+        }
+
+        if self.debug {
+            println!("\n\nITERATION {} price {:?}:", self.observations.len() + 1, observation);
+        }
+
+        // Comparisons with the previous price need to know what it was.
+        let band = *Observation::bands(&[self.observations.as_slice(), &[observation]].concat())
+            .last().unwrap();
+        let nodes = step(self.frontier().to_vec(), band, &self.options)?;
+        // Only keep the price once it has been taken, so that there is always
+        // one more frontier than there are prices.
+        self.observations.push(observation);
+        self.frontiers.push(merge(nodes));
+        self.completed.take();
+
+        if self.debug {
+            println!("{:#?}", self.frontier());
+        }
+        return Ok(());
+    }
+
+    /// Add the prices for the next few half-days.
+    pub fn extend(&mut self, observations: Vec<Observation>) -> Result<(), CalcError> {
+        if self.observations.len() + observations.len() > MAX_HALF_DAYS as usize {
+            return Err(CalcError::TooManyPrices(self.observations.len() + observations.len()));
+        }
+
+        for observation in observations {
+            self.push(observation)?;
+        }
+        return Ok(());
+    }
+
+    /// Remove the latest price, returning it. If there are no prices, nothing happens.
+    pub fn undo(&mut self) -> Option<Observation> {
+        let observation = self.observations.pop()?;
+        self.frontiers.pop();
+        self.completed.take();
+        return Some(observation);
+    }
+
+    /// Replace the latest price with another. If there are no prices, this is
+    /// the same as `push`.
+    /// Unlike `push`, a price that doesn't match any pattern is rejected with
+    /// `CalcError::NoMatchingPattern` if the prices before it did match. If the
+    /// price is rejected, the latest price is kept as it was.
+    pub fn replace(&mut self, observation: Observation) -> Result<(), CalcError> {
+        let (previous, frontier) = match self.observations.pop() {
+            Some(previous) => (previous, self.frontiers.pop().expect("BUG: No frontier!")),
+            None => return self.push(observation),
+        };
+
+        let matched = !self.frontier().is_empty();
+        let result = match self.push(observation) {
+            Ok(()) if matched && self.frontier().is_empty() => {
+                self.undo();
+                Err(CalcError::NoMatchingPattern)
+            }
+            result => result,
+        };
+        if result.is_err() {
+            // Put the previous price back, so that a rejected price changes nothing.
+            self.observations.push(previous);
+            self.frontiers.push(frontier);
+            self.completed.take();
+        }
+        return result;
+    }
+
+    /// Get the probability of each pattern given the prices so far.
+    pub fn analysis(&self) -> Result<Analysis, CalcError> {
+        return Analysis::new(self.nodes()?);
+    }
+
+    /// Get the nodes that are possible for the next half-day, which may be empty.
+    pub(crate) fn frontier(&self) -> &[Node] {
+        self.frontiers.last().expect("BUG: No frontier!")
+    }
+
+    /// Get the nodes that were possible before each price, and after the latest.
+    pub(crate) fn frontiers(&self) -> &[Vec<Node>] {
+        &self.frontiers
+    }
+
+    /// Get the nodes that are possible for the next half-day, failing if there
+    /// are none.
+    pub(crate) fn nodes(&self) -> Result<&[Node], CalcError> {
+        let nodes = self.frontier();
+        if nodes.is_empty() {
+            return Err(CalcError::NoMatchingPattern);
+        }
+        return Ok(nodes);
+    }
+
+    /// Continue traversing the current nodes with unknown prices until the end of
+    /// the week, returning the final nodes, each of which holds the histories of
+    /// the paths that lead to it. These are kept until the prices change.
+    pub(crate) fn complete(&self) -> Result<&[Node], CalcError> {
+        if let Some(nodes) = self.completed.get() {
+            return Ok(nodes);
+        }
+        let mut nodes = self.nodes()?.to_vec();
+        while nodes.first().is_some_and(|n| n.path().len() < MAX_HALF_DAYS as usize) {
+            nodes = step(nodes, None, &self.options)?;
+        }
+        return Ok(self.completed.get_or_init(|| nodes));
+    }
+
+    /// Get a copy of this calculator as it was before the price on the given
+    /// half-day, so that different prices can be tried from there.
+    pub(crate) fn rewound(&self, half_day: usize) -> Self {
+        let mut calculator = self.clone();
+        calculator.observations.truncate(half_day);
+        calculator.frontiers.truncate(half_day + 1);
+        calculator.completed.take();
+        // Trying other prices isn't worth dumping.
+        calculator.debug = false;
+        return calculator;
+    }
+}
+
+/// Move all the given nodes on by one half-day, with the given price.
+pub(crate) fn step(nodes: Vec<Node>, price: Option<PriceBand>,
+                   options: &Options) -> Result<Vec<Node>, CalcError> {
+    let mut new_nodes = Vec::new();
+    for node in nodes {
+        new_nodes.extend(node.children(price, options)?);
+    }
+    return Ok(new_nodes);
+}
+
+/// Merge the nodes that are in the same state. With an error rate, each price
+/// splits a path in two, depending on whether it was mistyped, so without this
+/// the number of paths would double with every price.
+fn merge(nodes: Vec<Node>) -> Vec<Node> {
+    let mut merged: Vec<Node> = Vec::with_capacity(nodes.len());
+    let mut states: HashMap<State, usize> = HashMap::with_capacity(nodes.len());
+    for node in nodes {
+        match states.entry(node.state()) {
+            Entry::Occupied(entry) => merged[*entry.get()].merge(node),
+            Entry::Vacant(entry) => {
+                entry.insert(merged.len());
+                merged.push(node);
+            }
+        }
+    }
+    return merged;
+}
@@ -0,0 +1,82 @@
+use crate::analysis::Analysis;
+use crate::calculator::Calculator;
+use crate::error::CalcError;
+use crate::observation::Observation;
+use crate::options::Options;
+use crate::pattern::PatternDistribution;
+use crate::traverse;
+
+/// A set of prices which, if they were typos, would explain why the prices
+/// don't match any pattern.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Suspect {
+    /// The indices of the suspect half-days, where 0 is Monday morning.
+    pub half_days: Vec<usize>,
+    /// The analysis of the remaining prices, ignoring the suspect ones.
+    pub analysis: Analysis,
+}
+
+/// Find the smallest sets of prices which, if ignored, would make the rest
+/// match at least one pattern. Only sets of one or two prices are considered.
+/// The results are sorted with the most plausible first, i.e. those where the
+/// remaining prices are most likely to be seen.
+/// If the prices already match a pattern, there are no suspects.
+pub fn diagnose(last_week: impl Into<PatternDistribution>, base_price: Option<u32>,
+                prices: Vec<Observation>, options: &Options) -> Result<Vec<Suspect>, CalcError> {
+    return traverse(last_week, base_price, prices, options, false)?.diagnose();
+}
+
+impl Calculator {
+    /// Find the smallest sets of prices which, if ignored, would make the rest
+    /// match at least one pattern, most plausible first.
+    pub fn diagnose(&self) -> Result<Vec<Suspect>, CalcError> {
+        match self.analysis() {
+            Ok(_) => return Ok(Vec::new()),
+            Err(CalcError::NoMatchingPattern) => {}
+            Err(e) => return Err(e),
+        }
+
+        let prices = self.observations();
+        let known: Vec<usize> = (0..prices.len())
+            .filter(|i| prices[*i].is_known())
+            .collect();
+
+        // Try ignoring each price on its own first, and only then pairs.
+        let singles: Vec<Vec<usize>> = known.iter().map(|i| vec![*i]).collect();
+        let mut pairs = Vec::new();
+        for (n, i) in known.iter().enumerate() {
+            for j in known.iter().skip(n + 1) {
+                pairs.push(vec![*i, *j]);
+            }
+        }
+
+        for candidates in [singles, pairs] {
+            let mut results = Vec::new();
+            for half_days in candidates {
+                // Everything before the first ignored price is unaffected.
+                let mut calculator = self.rewound(half_days[0]);
+                for (i, price) in prices.iter().enumerate().skip(half_days[0]) {
+                    if half_days.contains(&i) {
+                        calculator.push(Observation::Unknown)?;
+                    } else {
+                        calculator.push(*price)?;
+                    }
+                }
+                match calculator.analysis() {
+                    Ok(analysis) => results.push(Suspect { half_days, analysis }),
+                    Err(CalcError::NoMatchingPattern) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if !results.is_empty() {
+                results.sort_by(|a, b| b.analysis.evidence().total_cmp(&a.analysis.evidence()));
+                return Ok(results);
+            }
+        }
+
+        // Something is more seriously wrong.
+        return Err(CalcError::NoMatchingPattern);
+    }
+}
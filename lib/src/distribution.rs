@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use crate::calculator::Calculator;
+use crate::error::CalcError;
+use crate::node::{smooth, Node, Smoothed};
+use crate::observation::Observation;
+use crate::options::Options;
+use crate::pattern::PatternDistribution;
+use crate::traverse;
+
+/// The probability of each possible price on a single half-day.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriceDistribution {
+    /// The index of the half-day, where 0 is Monday morning.
+    pub half_day: usize,
+    /// Each possible price and its probability, in ascending order of price.
+    pub probabilities: Vec<(u32, f64)>,
+}
+
+impl PriceDistribution {
+    /// The probability of exactly the given price.
+    pub fn probability(&self, price: u32) -> f64 {
+        self.probabilities.iter()
+            .find(|(p, _)| *p == price)
+            .map_or(0.0, |(_, prob)| *prob)
+    }
+
+    /// The expected (mean) price.
+    pub fn expected(&self) -> f64 {
+        self.probabilities.iter()
+            .map(|(price, prob)| *price as f64 * prob)
+            .sum()
+    }
+
+    /// The median price, i.e. the lowest price with at least a 50% chance of
+    /// the real price being no higher.
+    pub fn median(&self) -> u32 {
+        let mut cumulative = 0.0;
+        for (price, prob) in self.probabilities.iter() {
+            cumulative += prob;
+            if cumulative >= 0.5 {
+                return *price;
+            }
+        }
+        return self.probabilities.last().map_or(0, |(price, _)| *price);
+    }
+}
+
+/// Calculate the distribution of prices on every half-day of the week, taking
+/// into account every possible path through the pattern trees.
+/// Known prices are certain; missing and future prices are inferred, as are
+/// prices that are only known to be within a range. With an error rate, known
+/// prices are mixed with what they probably were if they were mistyped.
+pub fn distribution(last_week: impl Into<PatternDistribution>, base_price: Option<u32>,
+                    prices: Vec<Observation>, options: &Options) -> Result<Vec<PriceDistribution>, CalcError> {
+    return traverse(last_week, base_price, prices, options, false)?.distribution();
+}
+
+impl Calculator {
+    /// Calculate the distribution of prices on every half-day of the week.
+    pub fn distribution(&self) -> Result<Vec<PriceDistribution>, CalcError> {
+        return distribution_of(self.complete()?, self.observations(), self.options());
+    }
+}
+
+/// Calculate the distribution of prices on every half-day of the week, given
+/// the nodes at the end of the week.
+pub(crate) fn distribution_of(nodes: &[Node], prices: &[Observation],
+                              options: &Options) -> Result<Vec<PriceDistribution>, CalcError> {
+    let bands = Observation::bands(prices);
+    let mut probabilities: Vec<BTreeMap<u32, f64>> = vec![BTreeMap::new(); nodes[0].path().len()];
+    for Smoothed { half_day, step: taken, smoothed: step, prob } in smooth(nodes) {
+        // Known prices are certain, unless they could have been mistyped.
+        if let (Some(_), 0.0) = (prices.get(half_day).and_then(Observation::price), options.error_rate) {
+            continue;
+        }
+
+        // Later prices in the same phase can rule out some of the factors and
+        // make others likelier, so take account of them.
+        let band = bands.get(half_day).copied().flatten();
+        let mistyped = match band {
+            Some(_) => step.mistyped,
+            None => 0.0,
+        };
+
+        // Only the prices in the observed range are still possible, so scale
+        // them up to make up for the ones that aren't.
+        let step_probabilities = step.price_probabilities();
+        let right: Vec<(u32, f64)> = match band {
+            Some(band) => step_probabilities.iter().copied()
+                .filter(|(price, _)| band.min <= *price && *price <= band.max)
+                .collect(),
+            None => step_probabilities.clone(),
+        };
+        add(&mut probabilities[half_day], &right, prob * (1.0 - mistyped));
+
+        // If the price was mistyped, the real one could be anything this step
+        // allows, without the observed price to narrow it.
+        if mistyped > 0.0 {
+            match step.band {
+                Some(_) => add(&mut probabilities[half_day], &taken.price_probabilities(), prob * mistyped),
+                None => add(&mut probabilities[half_day], &step_probabilities, prob * mistyped),
+            }
+        }
+    }
+
+    return Ok(probabilities.into_iter().enumerate().map(|(half_day, probabilities)| {
+        let probabilities = match (prices.get(half_day).and_then(Observation::price), options.error_rate) {
+            (Some(price), 0.0) => vec![(price, 1.0)],
+            _ => probabilities.into_iter().collect(),
+        };
+        PriceDistribution { half_day, probabilities }
+    }).collect());
+}
+
+/// Add the given probabilities of each price, scaled to add up to the given
+/// weight, to a running distribution. Nothing is added if none are possible.
+fn add(probabilities: &mut BTreeMap<u32, f64>, path_probabilities: &[(u32, f64)], weight: f64) {
+    let path_total: f64 = path_probabilities.iter().map(|(_, prob)| prob).sum();
+    if path_total <= 0.0 || weight <= 0.0 {
+        return;
+    }
+    for (price, prob) in path_probabilities {
+        *probabilities.entry(*price).or_insert(0.0) += weight * prob / path_total;
+    }
+}
@@ -0,0 +1,44 @@
+use std::fmt::{Display, Formatter};
+
+use crate::session::SESSION_VERSION;
+
+/// The ways in which running the calculator can fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CalcError {
+    /// The base price was outside the range the game uses (90-110).
+    InvalidBasePrice(u32),
+    /// More prices were given than there are half-days in a week.
+    TooManyPrices(usize),
+    /// The options were outside their allowed ranges.
+    InvalidOptions(&'static str),
+    /// A distribution of patterns was not a valid probability distribution.
+    InvalidDistribution(&'static str),
+    /// A saved session was written by a newer version of the library.
+    UnsupportedVersion(u32),
+    /// The prices did not match any pattern.
+    NoMatchingPattern,
+    /// The pattern trees were inconsistent with themselves; this is a bug.
+    Inconsistent(&'static str),
+}
+
+impl Display for CalcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CalcError::InvalidBasePrice(price) => write!(
+                f, "The base price {} is invalid; it must be between 90 and 110.", price),
+            CalcError::TooManyPrices(num) => write!(
+                f, "There are only 12 prices in a week, but {} were given.", num),
+            CalcError::InvalidOptions(msg) => write!(f, "Invalid options: {}", msg),
+            CalcError::InvalidDistribution(msg) => write!(f, "Invalid distribution: {}", msg),
+            CalcError::UnsupportedVersion(version) => write!(
+                f, "The saved session has version {}, but only versions up to {} \
+                    are supported.", version, SESSION_VERSION),
+            CalcError::NoMatchingPattern => write!(
+                f, "These prices did not match any known pattern. Either your \
+                    numbers are wrong, or there is a bug."),
+            CalcError::Inconsistent(msg) => write!(f, "BUG: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CalcError {}
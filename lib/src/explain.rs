@@ -0,0 +1,97 @@
+use std::fmt::{Display, Formatter};
+
+use crate::calculator::Calculator;
+use crate::error::CalcError;
+use crate::node::Node;
+use crate::observation::{Observation, PriceBand};
+use crate::options::Options;
+use crate::pattern::{Pattern, PatternDistribution};
+use crate::{traverse, HALF_DAY_NAMES};
+
+/// The reason a pattern was ruled out: the first price it could not produce.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Exclusion {
+    /// The pattern that was ruled out.
+    pub pattern: Pattern,
+    /// The index of the half-day, where 0 is Monday morning.
+    pub half_day: usize,
+    /// The range of prices observed on that half-day.
+    pub price: PriceBand,
+    /// The ranges of prices the pattern allowed on that half-day, in ascending order.
+    pub allowed: Vec<PriceBand>,
+}
+
+impl Display for Exclusion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} excluded at {}: ", self.pattern, HALF_DAY_NAMES[self.half_day])?;
+        let below = self.allowed.iter().rev().find(|band| band.max < self.price.min);
+        let above = self.allowed.iter().find(|band| band.min > self.price.max);
+        match (below, above) {
+            (Some(below), Some(above)) => write!(
+                f, "{} falls between allowed ranges {}-{} and {}-{}",
+                self.price, below.min, below.max, above.min, above.max),
+            (Some(below), None) => write!(f, "{} exceeds max {}", self.price, below.max),
+            (None, Some(above)) => write!(f, "{} is below min {}", self.price, above.min),
+            (None, None) => write!(f, "{} is not allowed", self.price),
+        }
+    }
+}
+
+/// Explain why each pattern that is no longer possible was ruled out.
+/// Unlike the other calculations, this still succeeds if no pattern matches.
+pub fn explain(last_week: impl Into<PatternDistribution>, base_price: Option<u32>,
+               prices: Vec<Observation>, options: &Options) -> Result<Vec<Exclusion>, CalcError> {
+    return Ok(traverse(last_week, base_price, prices, options, false)?.explain());
+}
+
+impl Calculator {
+    /// Explain why each pattern that is no longer possible was ruled out.
+    /// Unlike the other calculations, this still works if no pattern matches.
+    pub fn explain(&self) -> Vec<Exclusion> {
+        let bands = Observation::bands(self.observations());
+        let mut results = Vec::new();
+        for (half_day, (price, frontiers)) in bands.into_iter().zip(self.frontiers().windows(2)).enumerate() {
+            let (before, after) = (&frontiers[0], &frontiers[1]);
+
+            // Any pattern that was possible before this price but not after has
+            // just been ruled out by it.
+            for pattern in Pattern::ALL {
+                let was_possible = before.iter().any(|n| n.value().0 == pattern);
+                let is_possible = after.iter().any(|n| n.value().0 == pattern);
+                if let (true, false, Some(p)) = (was_possible, is_possible, price) {
+                    results.push(Exclusion {
+                        pattern,
+                        half_day,
+                        price: p,
+                        allowed: allowed_bands(before, pattern),
+                    });
+                }
+            }
+        }
+
+        return results;
+    }
+}
+
+/// Find the ranges of prices allowed by the given pattern across the given
+/// nodes, merging any that overlap.
+fn allowed_bands(nodes: &[Node], pattern: Pattern) -> Vec<PriceBand> {
+    let mut bands: Vec<PriceBand> = nodes.iter()
+        .filter(|n| n.value().0 == pattern)
+        .map(|n| {
+            let (min, max) = n.step().price_range();
+            PriceBand { min, max }
+        })
+        .collect();
+    bands.sort_by_key(|band| band.min);
+
+    let mut merged: Vec<PriceBand> = Vec::new();
+    for band in bands {
+        match merged.last_mut() {
+            Some(last) if band.min <= last.max + 1 => last.merge(band),
+            _ => merged.push(band),
+        }
+    }
+    return merged;
+}
@@ -0,0 +1,148 @@
+use crate::error::CalcError;
+use crate::history::{likelihoods, smooth};
+use crate::options::Options;
+use crate::pattern::{Pattern, PatternDistribution};
+use crate::session::Week;
+use crate::transition::TransitionMatrix;
+
+/// The most rounds of expectation-maximisation to run before giving up on
+/// the estimate settling down.
+const MAX_ITERATIONS: usize = 1000;
+/// Stop once no transition changes by more than this in a round.
+const TOLERANCE: f64 = 1e-9;
+/// The number of standard errors either side of an estimate that gives a
+/// 95% confidence interval, before Wilson's correction.
+const Z_95: f64 = 1.96;
+
+/// The estimated chance of one pattern following another.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransitionEstimate {
+    /// Last week's pattern.
+    pub from: Pattern,
+    /// This week's pattern.
+    pub to: Pattern,
+    /// The estimated chance of `to` following `from`.
+    pub probability: f64,
+    /// The lower end of the 95% confidence interval.
+    pub min: f64,
+    /// The upper end of the 95% confidence interval.
+    pub max: f64,
+    /// The chance from the New Horizons table, for comparison.
+    pub datamined: f64,
+    /// The expected number of weeks that followed `from`, which the estimate
+    /// is based on. This is fractional since patterns are rarely certain.
+    pub weeks: f64,
+}
+
+impl TransitionEstimate {
+    /// Whether the New Horizons table is within the confidence interval.
+    pub fn agrees(&self) -> bool {
+        self.min <= self.datamined && self.datamined <= self.max
+    }
+}
+
+/// The transitions between patterns that best explain some recorded weeks.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransitionFit {
+    /// The fitted transitions. When last week is unknown, these use the
+    /// stationary distribution.
+    pub transitions: TransitionMatrix,
+    /// Every transition with its confidence interval, by last week's pattern
+    /// and then this week's, both in the order of `Pattern::ALL`.
+    pub estimates: Vec<TransitionEstimate>,
+    /// The number of rounds of expectation-maximisation that were needed.
+    pub iterations: usize,
+}
+
+/// Estimate the chance of each pattern following each other pattern from
+/// several runs of consecutive weeks, e.g. the histories of different islands.
+/// Weeks may be missing some or all of their prices.
+/// The transitions in the options are used as the starting point, and
+/// transitions from a pattern that never appears are left as they were.
+/// If `first_week` is set in the options, every history starts from the first
+/// week on its island.
+pub fn fit_transitions(histories: &[Vec<Week>], options: &Options) -> Result<TransitionFit, CalcError> {
+    options.validate()?;
+    let histories = histories.iter()
+        .map(|weeks| likelihoods(weeks, options))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The estimate so far is kept in the options, so that it is used for the
+    // first week of each history too.
+    let mut options = options.clone();
+    let mut counts = [[0.0; 4]; 4];
+    let mut iterations = 0;
+    while iterations < MAX_ITERATIONS {
+        iterations += 1;
+
+        // Expectation: how often each transition probably happened.
+        counts = [[0.0; 4]; 4];
+        let first = options.priors(&PatternDistribution::default())?;
+        for likelihoods in &histories {
+            let (_, history_counts) = smooth(likelihoods, &first, &options.transitions)?;
+            for (count, extra) in counts.iter_mut().flatten().zip(history_counts.iter().flatten()) {
+                *count += extra;
+            }
+        }
+
+        // Maximisation: the transitions that best explain those counts.
+        let rows = Pattern::ALL.map(|from| {
+            let row = &counts[from.index()];
+            let total: f64 = row.iter().sum();
+            if total > 0.0 {
+                row.map(|count| count / total)
+            } else {
+                Pattern::ALL.map(|to| options.transitions.probability(Some(from), to))
+            }
+        });
+        let fitted = TransitionMatrix::new(rows)?;
+
+        let change = Pattern::ALL.iter()
+            .flat_map(|from| Pattern::ALL.map(|to| {
+                (fitted.probability(Some(*from), to) - options.transitions.probability(Some(*from), to)).abs()
+            }))
+            .fold(0.0, f64::max);
+        options.transitions = fitted;
+        if change < TOLERANCE {
+            break;
+        }
+    }
+
+    // Wilson intervals, treating the expected counts as if they were observed.
+    let mut estimates = Vec::with_capacity(16);
+    for from in Pattern::ALL {
+        let weeks: f64 = counts[from.index()].iter().sum();
+        for to in Pattern::ALL {
+            let probability = options.transitions.probability(Some(from), to);
+            let (min, max) = wilson(probability, weeks);
+            estimates.push(TransitionEstimate {
+                from,
+                to,
+                probability,
+                min,
+                max,
+                datamined: to.prior(Some(from)),
+                weeks,
+            });
+        }
+    }
+
+    return Ok(TransitionFit { transitions: options.transitions, estimates, iterations });
+}
+
+/// Get the Wilson score interval for a proportion seen in the given number of
+/// weeks. Unlike the usual interval, this doesn't shrink to nothing when the
+/// proportion is 0 or 1, which is common when there are only a few weeks.
+fn wilson(probability: f64, weeks: f64) -> (f64, f64) {
+    if weeks <= 0.0 {
+        return (0.0, 1.0);
+    }
+    let z2 = Z_95 * Z_95;
+    let scale = 1.0 + z2 / weeks;
+    let centre = (probability + z2 / (2.0 * weeks)) / scale;
+    let error = Z_95 / scale
+        * (probability * (1.0 - probability) / weeks + z2 / (4.0 * weeks * weeks)).sqrt();
+    return ((centre - error).max(0.0), (centre + error).min(1.0));
+}
@@ -0,0 +1,62 @@
+use crate::calculator::Calculator;
+use crate::node::Node;
+use crate::error::CalcError;
+use crate::observation::{Observation, PriceBand};
+use crate::options::Options;
+use crate::pattern::{Pattern, PatternDistribution};
+use crate::traverse;
+
+/// The possible prices for a single remaining half-day.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HalfDayForecast {
+    /// The index of the half-day, where 0 is Monday morning.
+    pub half_day: usize,
+    /// The range of prices across all possible patterns.
+    pub overall: PriceBand,
+    /// The range of prices for each possible pattern, in the order of `Pattern::ALL`.
+    pub patterns: Vec<(Pattern, PriceBand)>,
+}
+
+/// Forecast the range of prices for every half-day after the given prices,
+/// both overall and for each pattern that is still possible.
+pub fn forecast(last_week: impl Into<PatternDistribution>, base_price: Option<u32>,
+                prices: Vec<Observation>, options: &Options) -> Result<Vec<HalfDayForecast>, CalcError> {
+    return traverse(last_week, base_price, prices, options, false)?.forecast();
+}
+
+impl Calculator {
+    /// Forecast the range of prices for every remaining half-day.
+    pub fn forecast(&self) -> Result<Vec<HalfDayForecast>, CalcError> {
+        let first = self.observations().len();
+        let nodes = self.complete()?;
+
+        return Ok((first..nodes[0].path().len())
+            .map(|half_day| bands(half_day, nodes))
+            .collect());
+    }
+}
+
+/// Collect the price bands of the given complete paths on one half-day.
+fn bands(half_day: usize, nodes: &[Node]) -> HalfDayForecast {
+    let mut overall: Option<PriceBand> = None;
+    let mut patterns: [Option<PriceBand>; 4] = [None; 4];
+
+    for node in nodes {
+        let (pattern, _) = node.value();
+        let (min, max) = node.path()[half_day].price_range();
+        let band = PriceBand { min, max };
+
+        overall.get_or_insert(band).merge(band);
+        patterns[pattern.index()].get_or_insert(band).merge(band);
+    }
+
+    HalfDayForecast {
+        half_day,
+        overall: overall.expect("BUG: No nodes to forecast!"),
+        patterns: Pattern::ALL.iter()
+            .zip(patterns)
+            .filter_map(|(pattern, band)| band.map(|b| (*pattern, b)))
+            .collect(),
+    }
+}
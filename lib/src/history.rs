@@ -0,0 +1,121 @@
+use crate::calculator::Calculator;
+use crate::error::CalcError;
+use crate::options::Options;
+use crate::pattern::{Pattern, PatternDistribution};
+use crate::session::Week;
+use crate::transition::TransitionMatrix;
+
+/// The expected number of times each pattern followed each other pattern,
+/// indexed by the earlier week's pattern and then the later one's.
+pub(crate) type TransitionCounts = [[f64; 4]; 4];
+
+/// Calculate the chance of each pattern in a run of consecutive weeks, oldest
+/// first. Each week's prices also tell us about the weeks either side of it,
+/// so incomplete weeks are more accurate than when analysed on their own.
+/// `before` is what is known about the week before the first, unless the first
+/// is the first week on the island (`first_week` in the options).
+/// To analyse the current week, pass the result for the week before it to
+/// `Calculator::new` as last week.
+pub fn history(weeks: &[Week], before: impl Into<PatternDistribution>,
+               options: &Options) -> Result<Vec<PatternDistribution>, CalcError> {
+    let before = before.into();
+    options.validate()?;
+    let likelihoods = likelihoods(weeks, options)?;
+    let (results, _) = smooth(&likelihoods, &options.priors(&before)?, &options.transitions)?;
+    return Ok(results.into_iter().map(distribution).collect());
+}
+
+/// Calculate how well each week's prices fit each pattern, up to a constant
+/// factor. This doesn't depend on the transitions between weeks.
+pub(crate) fn likelihoods(weeks: &[Week], options: &Options) -> Result<Vec<[f64; 4]>, CalcError> {
+    return weeks.iter().map(|week| likelihood(week, options)).collect();
+}
+
+/// Calculate the chance of each pattern in every week given all the weeks and
+/// the chance of each pattern in the first week before seeing any prices,
+/// along with the expected number of times each transition happened.
+pub(crate) fn smooth(likelihoods: &[[f64; 4]], first: &PatternDistribution,
+                     transitions: &TransitionMatrix) -> Result<(Vec<[f64; 4]>, TransitionCounts), CalcError> {
+    // Forwards: the chance of each pattern given this week and all before it.
+    let mut filtered: Vec<[f64; 4]> = Vec::with_capacity(likelihoods.len());
+    let mut prior = first.probabilities().map(|(_, prob)| prob);
+    for likelihood in likelihoods {
+        let current = normalise(multiply(&prior, likelihood))?;
+        prior = advance(transitions, &current);
+        filtered.push(current);
+    }
+
+    // Backwards: also take account of all the weeks after this one.
+    let mut smoothed = vec![[0.0; 4]; likelihoods.len()];
+    let mut counts: TransitionCounts = [[0.0; 4]; 4];
+    let mut after = [1.0; 4];
+    for (i, likelihood) in likelihoods.iter().enumerate().rev() {
+        smoothed[i] = normalise(multiply(&filtered[i], &after))?;
+        let weighted = multiply(likelihood, &after);
+
+        if i > 0 {
+            // The chance of each pair of patterns for the previous week and this one.
+            let mut pairs = [[0.0; 4]; 4];
+            for prev in Pattern::ALL {
+                for pattern in Pattern::ALL {
+                    pairs[prev.index()][pattern.index()] = filtered[i - 1][prev.index()]
+                        * transitions.probability(Some(prev), pattern) * weighted[pattern.index()];
+                }
+            }
+            let total: f64 = pairs.iter().flatten().sum();
+            for (count, pair) in counts.iter_mut().flatten().zip(pairs.iter().flatten()) {
+                *count += pair / total;
+            }
+        }
+
+        after = normalise(Pattern::ALL.map(|prev| {
+            Pattern::ALL.iter()
+                .map(|pattern| transitions.probability(Some(prev), *pattern) * weighted[pattern.index()])
+                .sum()
+        }))?;
+    }
+    return Ok((smoothed, counts));
+}
+
+/// Calculate how well a week's prices fit each pattern, up to a constant factor.
+fn likelihood(week: &Week, options: &Options) -> Result<[f64; 4], CalcError> {
+    // With every pattern equally likely beforehand, the chance of each pattern
+    // afterwards is proportional to how well it fits.
+    let options = Options {
+        transitions: options.transitions.with_unknown([0.25; 4])?,
+        first_week: false,
+        ..options.clone()
+    };
+    let mut calculator = Calculator::new(PatternDistribution::default(), week.base_price, options)?;
+    calculator.extend(week.observations.clone())?;
+    let analysis = calculator.analysis()?;
+    return Ok(Pattern::ALL.map(|pattern| analysis.probability(pattern)));
+}
+
+/// Move a distribution of patterns on by one week.
+fn advance(transitions: &TransitionMatrix, current: &[f64; 4]) -> [f64; 4] {
+    return Pattern::ALL.map(|pattern| {
+        Pattern::ALL.iter()
+            .map(|prev| current[prev.index()] * transitions.probability(Some(*prev), pattern))
+            .sum()
+    });
+}
+
+fn multiply(a: &[f64; 4], b: &[f64; 4]) -> [f64; 4] {
+    return [a[0] * b[0], a[1] * b[1], a[2] * b[2], a[3] * b[3]];
+}
+
+/// Scale the chances to add up to 1, failing if they are all zero, since then
+/// the weeks can't follow on from each other.
+fn normalise(probabilities: [f64; 4]) -> Result<[f64; 4], CalcError> {
+    let total: f64 = probabilities.iter().sum();
+    if total <= 0.0 {
+        return Err(CalcError::NoMatchingPattern);
+    }
+    return Ok(probabilities.map(|prob| prob / total));
+}
+
+fn distribution(probabilities: [f64; 4]) -> PatternDistribution {
+    let pairs: Vec<(Pattern, f64)> = Pattern::ALL.iter().copied().zip(probabilities).collect();
+    return PatternDistribution::new(&pairs).expect("BUG: Invalid pattern distribution!");
+}
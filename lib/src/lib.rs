@@ -1,57 +1,67 @@
-use std::collections::HashMap;
+#![allow(clippy::needless_return, clippy::new_ret_no_self)]
 
+mod analysis;
+mod calculator;
+mod diagnose;
+mod distribution;
+mod error;
+mod explain;
+mod fit;
+mod forecast;
+mod history;
+mod maximum;
+mod next_week;
 mod node;
+mod observation;
+mod options;
+mod parameters;
 mod pattern;
+mod phase;
+mod session;
 #[cfg(test)]
 mod tests;
+mod timing;
+mod transition;
 
-pub use pattern::Pattern;
+pub use analysis::Analysis;
+pub use calculator::Calculator;
+pub use diagnose::{diagnose, Suspect};
+pub use distribution::{distribution, PriceDistribution};
+pub use error::CalcError;
+pub use explain::{explain, Exclusion};
+pub use fit::{fit_transitions, TransitionEstimate, TransitionFit};
+pub use forecast::{forecast, HalfDayForecast};
+pub use history::history;
+pub use maximum::{max_price, MaxPriceDistribution};
+pub use next_week::next_week;
+pub use observation::{Observation, PriceBand};
+pub use options::Options;
+pub use parameters::{hidden_parameters, HiddenParameters, PhaseParameters, RateDistribution, RateRange};
+pub use pattern::{Pattern, PatternDistribution};
+pub use phase::{current_phase, PhaseState};
+pub use session::{Session, SESSION_VERSION, Week};
+pub use timing::{spike_timing, SpikeTiming};
+pub use transition::TransitionMatrix;
 
-use node::Node;
+/// Short names for each half-day of the week, in order.
+pub const HALF_DAY_NAMES: [&str; 12] = [
+    "Mon AM", "Mon PM", "Tue AM", "Tue PM", "Wed AM", "Wed PM",
+    "Thu AM", "Thu PM", "Fri AM", "Fri PM", "Sat AM", "Sat PM",
+];
 
-/// Run the calculator on the given data, returning a (possibly empty) list
-/// of potential patterns and associated probabilities, sorted in descending
-/// order of likelihood.
-pub fn run(prev_pattern: Option<Pattern>, base_price: u32,
-           prices: Vec<Option<u32>>, debug: bool) -> Vec<(Pattern, f64)> {
-    // Start off with the base set of pattern nodes.
-    let mut nodes = Node::new_set(base_price, prev_pattern);
-
-    if debug {
-        println!("\n\nINITIAL:\n{:#?}", nodes);
-    }
-
-    // Iterate through all the prices, constructing and traversing the pattern trees.
-    for (i, price) in prices.into_iter().enumerate() {
-        if debug {
-            println!("\n\nITERATION {} price {:?}:", i+1, price);
-        }
-        let mut new_nodes = Vec::new();
-        for node in nodes {
-            new_nodes.extend(node.children(price));
-        }
-        nodes = new_nodes;
-        if debug {
-            println!("{:#?}", nodes);
-        }
-    }
-
-    // Aggregate the resulting probabilities.
-    let mut probabilities: HashMap<Pattern, f64> = HashMap::with_capacity(4);
-    for node in nodes {
-        let (pattern, prob) = node.value();
-        *probabilities.entry(pattern).or_insert(0.0) += prob;
-    }
-
-    // Normalise the distribution.
-    let total: f64 = probabilities.values().sum();
-    for prob in probabilities.values_mut() {
-        *prob /= total;
-    }
-
-    // Sort descending.
-    let mut results: Vec<(Pattern, f64)> = probabilities.into_iter().collect();
-    results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+/// Run the calculator on the given data, returning the probability of each pattern.
+/// Last week's pattern can be given if known, or as a `PatternDistribution` if not.
+/// If the base price is unknown, every base price the game can choose is considered.
+pub fn run(last_week: impl Into<PatternDistribution>, base_price: Option<u32>, prices: Vec<Observation>,
+           options: &Options, debug: bool) -> Result<Analysis, CalcError> {
+    return traverse(last_week, base_price, prices, options, debug)?.analysis();
+}
 
-    return results;
+/// Create a calculator and give it all the given prices.
+fn traverse(last_week: impl Into<PatternDistribution>, base_price: Option<u32>, prices: Vec<Observation>,
+            options: &Options, debug: bool) -> Result<Calculator, CalcError> {
+    let mut calculator = Calculator::new(last_week, base_price, options.clone())?;
+    calculator.set_debug(debug);
+    calculator.extend(prices)?;
+    return Ok(calculator);
 }
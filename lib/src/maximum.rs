@@ -0,0 +1,117 @@
+use crate::calculator::Calculator;
+use crate::error::CalcError;
+use crate::node::Node;
+use crate::observation::Observation;
+use crate::options::Options;
+use crate::pattern::PatternDistribution;
+use crate::traverse;
+
+/// The distribution of the highest price over the rest of the week.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaxPriceDistribution {
+    /// Each possible maximum price and its probability, in ascending order of price.
+    pub probabilities: Vec<(u32, f64)>,
+}
+
+impl MaxPriceDistribution {
+    /// The probability that at least one remaining price is the given price or higher.
+    pub fn at_least(&self, price: u32) -> f64 {
+        self.probabilities.iter()
+            .filter(|(p, _)| *p >= price)
+            .map(|(_, prob)| prob)
+            .sum()
+    }
+
+    /// The expected (mean) maximum price.
+    pub fn expected(&self) -> f64 {
+        self.probabilities.iter()
+            .map(|(price, prob)| *price as f64 * prob)
+            .sum()
+    }
+}
+
+/// Calculate the distribution of the highest price over all the half-days after
+/// the given prices.
+/// Rather than combining the distributions of each half-day, this follows each
+/// path through the pattern trees, so it accounts for e.g. a spike only being
+/// able to happen once.
+/// If no half-days remain, the distribution is empty.
+pub fn max_price(last_week: impl Into<PatternDistribution>, base_price: Option<u32>,
+                 prices: Vec<Observation>, options: &Options) -> Result<MaxPriceDistribution, CalcError> {
+    return traverse(last_week, base_price, prices, options, false)?.max_price();
+}
+
+impl Calculator {
+    /// Calculate the distribution of the highest price over the remaining half-days.
+    pub fn max_price(&self) -> Result<MaxPriceDistribution, CalcError> {
+        let first = self.observations().len();
+        let nodes = self.complete()?;
+        let total: f64 = nodes.iter().map(|n| n.value().1).sum();
+
+        // Find the range of prices we need to consider.
+        let ranges: Vec<(u32, u32)> = nodes.iter()
+            .flat_map(|n| n.path().into_iter().skip(first))
+            .map(|step| step.price_range())
+            .collect();
+        let (min, max) = match (ranges.iter().map(|r| r.0).min(), ranges.iter().map(|r| r.1).max()) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Ok(MaxPriceDistribution { probabilities: Vec::new() }),
+        };
+
+        // cumulative[i] is the probability that the maximum is at most min + i.
+        let mut cumulative = vec![0.0; (max - min + 1) as usize];
+        for node in nodes.iter() {
+            let weight = node.value().1 / total;
+            for (sum, prob) in cumulative.iter_mut().zip(path_cumulative(node, first, min, max)) {
+                *sum += weight * prob;
+            }
+        }
+
+        // Convert back into individual probabilities.
+        let mut probabilities = Vec::new();
+        let mut previous = 0.0;
+        for (i, prob) in cumulative.into_iter().enumerate() {
+            if prob - previous > 0.0 {
+                probabilities.push((min + i as u32, prob - previous));
+            }
+            previous = prob;
+        }
+
+        return Ok(MaxPriceDistribution { probabilities });
+    }
+}
+
+/// For a single complete path, calculate the probability that the maximum price
+/// from the given half-day onwards is at most each price from `min` to `max`.
+fn path_cumulative(node: &Node, first: usize, min: u32, max: u32) -> Vec<f64> {
+    let mut cumulative = vec![1.0; (max - min + 1) as usize];
+    let history = node.path();
+
+    for (i, step) in history.iter().enumerate().skip(first) {
+        // Within a decreasing phase, each price is no higher than the last, so
+        // only the first one can be the maximum.
+        if i > first && step.decrement.is_some() && history[i - 1].phase == step.phase {
+            continue;
+        }
+        // Likewise, the prices either side of the Small Spike's peak are lower
+        // than it, so they can't be the maximum if the peak is still to come.
+        if step.peak.is_some_and(|peak| peak.neighbour) && history[first..].iter()
+            .any(|step| step.peak.is_some_and(|peak| !peak.neighbour)) {
+            continue;
+        }
+
+        // Otherwise, prices are chosen independently.
+        let probabilities = step.price_probabilities();
+        let mut step_cumulative = 0.0;
+        let mut next = probabilities.iter().peekable();
+        for (price, total) in (min..=max).zip(cumulative.iter_mut()) {
+            while let Some((_, prob)) = next.next_if(|(p, _)| *p <= price) {
+                step_cumulative += prob;
+            }
+            *total *= step_cumulative;
+        }
+    }
+
+    return cumulative;
+}
@@ -0,0 +1,21 @@
+use crate::calculator::Calculator;
+use crate::error::CalcError;
+use crate::observation::Observation;
+use crate::options::Options;
+use crate::pattern::PatternDistribution;
+use crate::traverse;
+
+/// Calculate the chance of each pattern next week, given the prices so far
+/// this week. The rest of this week doesn't need to be known.
+pub fn next_week(last_week: impl Into<PatternDistribution>, base_price: Option<u32>,
+                 prices: Vec<Observation>, options: &Options) -> Result<PatternDistribution, CalcError> {
+    return traverse(last_week, base_price, prices, options, false)?.next_week();
+}
+
+impl Calculator {
+    /// Calculate the chance of each pattern next week, given the prices so far.
+    pub fn next_week(&self) -> Result<PatternDistribution, CalcError> {
+        let this_week = PatternDistribution::from(&self.analysis()?);
+        return Ok(self.options().transitions.next_week(&this_week));
+    }
+}
@@ -1,13 +1,26 @@
 use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::RangeInclusive;
 use std::rc::Rc;
 
+mod arithmetic;
+mod density;
 mod factory;
+mod smoothing;
+mod typo;
 
-use crate::pattern::Pattern;
+use crate::error::CalcError;
+use crate::observation::PriceBand;
+use crate::options::Options;
+use crate::pattern::{Pattern, PatternDistribution};
 use factory::{ConditionalLengthNode, NodeFactory, SimpleNode, TerminatorNode};
+pub use arithmetic::Arithmetic;
+pub use density::Density;
+pub use smoothing::{smooth, Smoothed};
 
-const MAX_HALF_DAYS: i32 = 12;
-const FLOAT_CMP_EPSILON: f64 = 0.0001;
+pub const MAX_HALF_DAYS: i32 = 12;
+/// The base prices the game can choose from.
+pub const BASE_PRICES: RangeInclusive<u32> = 90..=110;
 
 /// A node in a pattern tree.
 /// To avoid verbose specification of the entire tree for each pattern (thousands
@@ -23,6 +36,8 @@ pub struct Node {
     name: String,
     /// The base price (turnip buying price on Sunday).
     base_price: u32,
+    /// How the game's arithmetic is reproduced.
+    arithmetic: Arithmetic,
     /// The probability of reaching this node.
     prob: f64,
     /// The minimum length of this phase before the next one.
@@ -35,14 +50,352 @@ pub struct Node {
     max_fac: f64,
     /// The optional range to decrease `min_fac` and `max_fac` by each iteration.
     decrement: Option<(f64, f64)>,
+    /// An amount added to the price after rounding, which the game uses to keep
+    /// some prices strictly below others.
+    price_offset: i32,
+    /// The Small Spike's peak rate, for the peak and the prices either side of
+    /// it, whose factors depend on it.
+    peak: Option<Peak>,
+    /// The distribution of the factor between `min_fac` and `max_fac`, if it
+    /// isn't uniform. After a decrement, the factor is the sum of several
+    /// uniform draws, so it is likelier towards the middle of its range.
+    density: Option<Rc<Density>>,
     /// The length of this phase so far.
     length: i32,
     /// The lengths of all previous phases.
     lengths: Vec<i32>,
+    /// The steps taken on all previous half-days.
+    history: Rc<History>,
     /// The phase that appears after this one.
     next_phase: Option<Rc<dyn NodeFactory>>,
 }
 
+/// The state of a node on a single half-day, kept so that complete paths
+/// through the tree can be examined after traversal.
+#[derive(Clone, Debug)]
+pub struct Step {
+    /// The name of the phase this half-day belongs to.
+    pub name: String,
+    /// The position of this half-day within its phase, starting from 1.
+    pub position: i32,
+    /// The base price (turnip buying price on Sunday).
+    pub base_price: u32,
+    /// How the game's arithmetic is reproduced.
+    pub arithmetic: Arithmetic,
+    /// The minimum factor of the base price allowed on this half-day.
+    pub min_fac: f64,
+    /// The maximum factor of the base price allowed on this half-day.
+    pub max_fac: f64,
+    /// An amount added to the price after rounding.
+    pub price_offset: i32,
+    /// The index of the phase this half-day belongs to.
+    pub phase: usize,
+    /// The range the factor decreases by each half-day, if it decreases.
+    pub decrement: Option<(f64, f64)>,
+    /// The Small Spike's peak rate, if this half-day depends on it.
+    pub peak: Option<Peak>,
+    /// The distribution of the factor, if it isn't uniform. Only the part
+    /// between `min_fac` and `max_fac` is used.
+    pub density: Option<Rc<Density>>,
+    /// The price this path took as right on this half-day, if any.
+    /// With an error rate, a price that fits is taken as right unless that
+    /// would change the rest of the path.
+    pub band: Option<PriceBand>,
+    /// The chance that the price on this half-day was entered wrongly, given
+    /// this path. This is only above zero with an error rate, and is 1 if the
+    /// path took the price as mistyped.
+    pub mistyped: f64,
+}
+
+/// The steps taken to reach a node, most recent first. Merged nodes got here
+/// in different ways, so each of their histories is kept, with how likely it
+/// was compared to the others.
+#[derive(Clone, Debug, Default)]
+enum History {
+    #[default]
+    Start,
+    Step(Step, Rc<History>),
+    Merged(Vec<(f64, Rc<History>)>),
+}
+
+impl History {
+    /// Get the most recent step. Merged histories only differ in which prices
+    /// were taken as mistyped, so any of them will do.
+    fn last(&self) -> Option<&Step> {
+        match self {
+            History::Start => None,
+            History::Step(step, _) => Some(step),
+            History::Merged(histories) => histories.first().and_then(|(_, h)| h.last()),
+        }
+    }
+
+    /// Get the steps of one of the merged histories, in order.
+    fn path(&self) -> Vec<&Step> {
+        let mut path = Vec::new();
+        let mut history = self;
+        loop {
+            match history {
+                History::Start => break,
+                History::Step(step, prev) => {
+                    path.push(step);
+                    history = prev;
+                }
+                History::Merged(histories) => match histories.first() {
+                    Some((_, first)) => history = first,
+                    None => break,
+                },
+            }
+        }
+        path.reverse();
+        return path;
+    }
+
+    /// Get the parts of the history that this one leads on from, given the
+    /// number of steps before the node it leads to, along with theirs.
+    fn earlier(&self, length: usize) -> Vec<(&History, usize)> {
+        match self {
+            History::Start => Vec::new(),
+            History::Step(_, prev) => vec![(prev.as_ref(), length - 1)],
+            History::Merged(histories) => histories.iter().map(|(_, history)| (history.as_ref(), length)).collect(),
+        }
+    }
+
+    /// Get the most recent step if its factor carries on to the next half-day.
+    /// Merged histories are all in the same state, so any of them will do.
+    fn continuing(&self) -> Option<&Step> {
+        self.last().filter(|step| step.decrement.is_some())
+    }
+
+    /// Count the merged histories, i.e. the number of different ways of getting here.
+    fn count(&self) -> usize {
+        match self {
+            History::Start => 1,
+            History::Step(_, prev) => prev.count(),
+            History::Merged(histories) => histories.iter().map(|(_, history)| history.count()).sum(),
+        }
+    }
+
+    /// Combine two histories with the given weights, which sum to 1.
+    fn merge(first: (f64, &Rc<History>), second: (f64, &Rc<History>)) -> Rc<History> {
+        let mut histories = Vec::new();
+        for (weight, history) in [first, second] {
+            match history.as_ref() {
+                History::Merged(merged) => histories.extend(merged.iter()
+                    .map(|(w, h)| (weight * w, h.clone()))),
+                _ => histories.push((weight, history.clone())),
+            }
+        }
+        return Rc::new(History::Merged(histories));
+    }
+
+    /// Narrow down the Small Spike's peak rate on the most recent steps that
+    /// depend on it, copying any that are shared with other nodes.
+    fn narrow_peak(history: &Rc<History>, arithmetic: Arithmetic, min: f64, max: f64) -> Rc<History> {
+        match history.as_ref() {
+            History::Step(step, prev) if step.peak.is_some() => {
+                let mut step = step.clone();
+                if let Some(peak) = step.peak.as_mut() {
+                    *peak = Peak { min, max, ..*peak };
+                    (step.min_fac, step.max_fac) = peak.factors(arithmetic);
+                }
+                Rc::new(History::Step(step, History::narrow_peak(prev, arithmetic, min, max)))
+            }
+            History::Merged(histories) => Rc::new(History::Merged(histories.iter()
+                .map(|(weight, h)| (*weight, History::narrow_peak(h, arithmetic, min, max)))
+                .collect())),
+            _ => history.clone(),
+        }
+    }
+}
+
+/// The Small Spike's peak rate, which is chosen before the spike starts. The
+/// prices either side of the peak are drawn below it, so they depend on it too.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Peak {
+    /// The lowest peak rate that is still possible.
+    pub min: f64,
+    /// The peak rate just above the highest that is still possible.
+    pub max: f64,
+    /// Whether this is for a price either side of the peak, rather than the
+    /// peak itself.
+    pub neighbour: bool,
+}
+
+impl Peak {
+    /// Get the range of factors for the half-day this is for.
+    fn factors(&self, arithmetic: Arithmetic) -> (f64, f64) {
+        if self.neighbour {
+            arithmetic.randfloat_to(1.40, (self.min, self.max))
+        } else {
+            (self.min, self.max)
+        }
+    }
+
+    /// Split the peak rate up by the price it produces, giving the chance of
+    /// each part.
+    fn split(&self, arithmetic: Arithmetic, base_price: u32) -> Vec<(Peak, f64)> {
+        let first = (self.min * base_price as f64).floor() as u32;
+        let last = (self.max * base_price as f64).ceil() as u32 + 1;
+        let mut parts = Vec::new();
+        for price in first..=last {
+            let (min, max) = arithmetic.factors_of(base_price, 0, PriceBand { min: price, max: price });
+            let (min, max) = (min.max(self.min), max.min(self.max));
+            if max > min {
+                parts.push((Peak { min, max, ..*self }, (max - min) / (self.max - self.min)));
+            }
+        }
+        return parts;
+    }
+
+    /// Get the probability of a price in the given band on the half-day this
+    /// is for, given the factors that would have if the peak rate were known.
+    ///
+    /// Either side of the peak, this is an approximation. The factor is really
+    /// drawn uniformly from 1.4 up to the peak rate, with the peak rate itself
+    /// uniform across each part, which is a mixture whose density falls off
+    /// logarithmically towards the top of the part. Instead, each part is
+    /// treated as if the factor were uniform from 1.4 up to the top of the
+    /// part. Each part only covers the rates for a single peak price, so this
+    /// only moves a little chance between the highest few prices.
+    fn chance(&self, arithmetic: Arithmetic, base_price: u32, price_offset: i32, band: PriceBand) -> f64 {
+        if !self.neighbour {
+            return band_chance(arithmetic, base_price, self.factors(arithmetic), None, price_offset, band);
+        }
+        // The price is drawn below the peak, so it is likelier to be low.
+        return self.split(arithmetic, base_price).iter()
+            .map(|(part, chance)| {
+                chance * band_chance(arithmetic, base_price, part.factors(arithmetic), None, price_offset, band)
+            })
+            .sum();
+    }
+}
+
+impl Step {
+    /// Get the range of prices that could appear on this half-day.
+    pub fn price_range(&self) -> (u32, u32) {
+        // Start from a slightly generous range, then trim off the prices at
+        // either end that can't happen.
+        let base_price = self.base_price as f64;
+        let offset = self.price_offset as f64;
+        let mut min = ((self.min_fac * base_price).floor() + offset).max(0.0) as u32;
+        let mut max = ((self.max_fac * base_price).ceil() + 1.0 + offset).max(0.0) as u32;
+        while min < max && self.chance(min) == 0.0 {
+            min += 1;
+        }
+        while max > min && self.chance(max) == 0.0 {
+            max -= 1;
+        }
+        (min, max)
+    }
+
+    /// Get the probability of each price on this half-day, from the distribution
+    /// of the factor between `min_fac` and `max_fac`, or below the peak rate
+    /// either side of the Small Spike's peak.
+    pub fn price_probabilities(&self) -> Vec<(u32, f64)> {
+        let (min, max) = self.price_range();
+        return (min..=max)
+            .map(|price| (price, self.chance(price)))
+            .filter(|(_, prob)| *prob > 0.0)
+            .collect();
+    }
+
+    /// Get the range of factors that this half-day could have had, given the
+    /// price this path took as right.
+    pub fn rates(&self) -> (f64, f64) {
+        let band = match self.band {
+            Some(band) => band,
+            None => return (self.min_fac, self.max_fac),
+        };
+        let (min, max) = self.arithmetic.factors_of(self.base_price, self.price_offset, band);
+        (min.max(self.min_fac), max.min(self.max_fac))
+    }
+
+    /// Get the distribution of the factor on this half-day.
+    fn density(&self) -> Density {
+        match &self.density {
+            Some(density) => density.as_ref().clone(),
+            None => Density::uniform(self.min_fac, self.max_fac),
+        }
+    }
+
+    /// Get the mean factor on this half-day, given its price.
+    pub fn mean_rate(&self) -> f64 {
+        let (min, max) = self.rates();
+        return self.density().mean(min, max).unwrap_or((min + max) / 2.0);
+    }
+
+    /// Get the range of factors on the next half-day, if it is in the same
+    /// phase, given the price on this one.
+    fn next_rates(&self) -> (f64, f64) {
+        match self.decrement {
+            Some(decrement) => self.arithmetic.decrease(self.rates(), decrement),
+            None => self.rates(),
+        }
+    }
+
+    /// Get the probability of the given price on this half-day.
+    fn chance(&self, price: u32) -> f64 {
+        let band = PriceBand { min: price, max: price };
+        match self.peak {
+            Some(peak) => peak.chance(self.arithmetic, self.base_price, self.price_offset, band),
+            None => band_chance(self.arithmetic, self.base_price, (self.min_fac, self.max_fac),
+                                self.density.as_deref(), self.price_offset, band),
+        }
+    }
+}
+
+/// Everything about a node that decides what can happen after it, so that
+/// nodes which only differ in how they got there can be merged.
+pub struct State {
+    pattern: Pattern,
+    name: String,
+    base_price: u32,
+    min_len: i32,
+    max_len: i32,
+    min_fac: f64,
+    max_fac: f64,
+    decrement: Option<(f64, f64)>,
+    price_offset: i32,
+    /// The Small Spike's peak rate, whether on this half-day or an earlier one,
+    /// since the price it produces may still be unknown.
+    peak: Option<(u64, u64)>,
+    density: Option<Rc<Density>>,
+    length: i32,
+    lengths: Vec<i32>,
+    next_phase: Option<Rc<dyn NodeFactory>>,
+}
+
+impl State {
+    /// The parts that can be compared directly, with factors by their bits.
+    fn key(&self) -> impl PartialEq + Hash + '_ {
+        (self.pattern, &self.name, self.base_price, (self.min_len, self.max_len),
+         (self.min_fac.to_bits(), self.max_fac.to_bits()),
+         self.decrement.map(|(min, max)| (min.to_bits(), max.to_bits())),
+         self.price_offset, self.peak, self.density.as_deref(), self.length, &self.lengths)
+    }
+
+    /// The phase after this one, which is shared by every node built from the
+    /// same tree.
+    fn next_phase(&self) -> Option<*const ()> {
+        self.next_phase.as_ref().map(|next| Rc::as_ptr(next) as *const ())
+    }
+}
+
+impl PartialEq for State {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key() && self.next_phase() == other.next_phase()
+    }
+}
+
+impl Eq for State {}
+
+impl Hash for State {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+        self.next_phase().hash(state);
+    }
+}
+
 impl Debug for Node {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?} {:.2}\n{}\nLength: {}\nRemaining Length: ({}, {})\n\
@@ -53,19 +406,40 @@ impl Debug for Node {
 }
 
 impl Node {
-    /// Get a fresh collection of starting nodes, representing all patterns.
-    pub fn new_set(base_price: u32, prev_pattern: Option<Pattern>) -> Vec<Self> {
+    /// Get a fresh collection of starting nodes, representing all patterns, given
+    /// the prior chance of each pattern.
+    /// If the base price is unknown, there is a set for every possible base price.
+    pub fn new_set(base_price: Option<u32>, priors: &PatternDistribution,
+                   arithmetic: Arithmetic) -> Result<Vec<Self>, CalcError> {
+        let base_price = match base_price {
+            Some(b) => b,
+            None => {
+                // Each base price is equally likely.
+                let chance = 1.0 / BASE_PRICES.count() as f64;
+                let mut nodes = Vec::new();
+                for b in BASE_PRICES {
+                    nodes.extend(Node::new_set(Some(b), priors, arithmetic)?);
+                }
+                for node in nodes.iter_mut() {
+                    node.prob *= chance;
+                }
+                return Ok(nodes);
+            }
+        };
+
         // Sanity-check the base price.
-        if base_price < 90 || base_price > 110 {
-            return Vec::new();
+        if !BASE_PRICES.contains(&base_price) {
+            return Err(CalcError::InvalidBasePrice(base_price));
         }
 
         let mut nodes = Vec::new();
-        nodes.push(Node::decreasing(base_price, prev_pattern));
-        nodes.extend(Node::random(base_price, prev_pattern));
-        nodes.extend(Node::small_spike(base_price, prev_pattern));
-        nodes.push(Node::large_spike(base_price, prev_pattern));
-        return nodes;
+        nodes.push(Node::decreasing(base_price, arithmetic, priors));
+        nodes.extend(Node::random(base_price, arithmetic, priors));
+        nodes.extend(Node::small_spike(base_price, arithmetic, priors));
+        nodes.push(Node::large_spike(base_price, arithmetic, priors));
+        // Patterns that can't happen this week would only get in the way.
+        nodes.retain(|node| node.prob > 0.0);
+        return Ok(nodes);
     }
 
     /// Get the pattern and probability of this Node.
@@ -73,124 +447,316 @@ impl Node {
         (self.pattern, self.prob)
     }
 
-    /// Given the next price, what possible children are there?
-    pub fn children(self, price: Option<u32>) -> Vec<Self> {
-        // If we have a known price, ensure it is within the given range.
-        if let Some(p) = price {
-            let (factor_min, factor_max) = self.factor_of(p);
-            // Make the comparison a little more forgiving, since floating
-            // point errors will hurt us otherwise.
-            if factor_max + FLOAT_CMP_EPSILON < self.min_fac
-                    || factor_min - FLOAT_CMP_EPSILON > self.max_fac
-            {
-                // Price doesn't match; no children returned.
-                return vec![];
-            }
+    /// Get the base price this node assumes.
+    pub fn base_price(&self) -> u32 {
+        self.base_price
+    }
+
+    /// Get the lengths of all previous phases.
+    pub fn lengths(&self) -> &[i32] {
+        &self.lengths
+    }
+
+    /// Get the steps taken on all previous half-days, by one of the ways this
+    /// node could have got here. The ways only differ before the nodes that
+    /// were merged into this one, so this is enough for anything after them.
+    pub fn path(&self) -> Vec<&Step> {
+        self.history.path()
+    }
+
+    /// Get the number of ways this node could have got here.
+    pub fn path_count(&self) -> usize {
+        self.history.count()
+    }
+
+    /// Get the history of a node that follows this one.
+    fn next_history(&self) -> Rc<History> {
+        Rc::new(History::Step(self.step(), self.history.clone()))
+    }
+
+    /// Get the state that decides what can happen after this node.
+    pub fn state(&self) -> State {
+        State {
+            pattern: self.pattern,
+            name: self.name.clone(),
+            base_price: self.base_price,
+            min_len: self.min_len,
+            max_len: self.max_len,
+            min_fac: self.min_fac,
+            max_fac: self.max_fac,
+            decrement: self.decrement,
+            price_offset: self.price_offset,
+            peak: self.peak.or_else(|| self.history.path().iter().rev().find_map(|step| step.peak))
+                .map(|peak| (peak.min.to_bits(), peak.max.to_bits())),
+            density: self.density.clone(),
+            length: self.length,
+            lengths: self.lengths.clone(),
+            next_phase: self.next_phase.clone(),
         }
+    }
+
+    /// Merge another node with the same state into this one. Such nodes only
+    /// differ in which prices were taken as mistyped, so both histories are
+    /// kept, weighted by how likely each node was.
+    pub fn merge(&mut self, other: Node) {
+        let prob = self.prob + other.prob;
+        if prob > 0.0 {
+            self.history = History::merge((self.prob / prob, &self.history),
+                                          (other.prob / prob, &other.history));
+        }
+        self.prob = prob;
+    }
+
+    /// Get the step this node represents for the current half-day.
+    pub fn step(&self) -> Step {
+        // Chained phases (such as spikes) are made of several nodes with the
+        // same name, so count the position across all of them.
+        let position = match self.history.last() {
+            Some(last) if last.name == self.name => last.position + 1,
+            _ => 1,
+        };
+
+        Step {
+            name: self.name.clone(),
+            position,
+            base_price: self.base_price,
+            arithmetic: self.arithmetic,
+            min_fac: self.min_fac,
+            max_fac: self.max_fac,
+            price_offset: self.price_offset,
+            phase: self.lengths.len(),
+            decrement: self.decrement,
+            peak: self.peak,
+            density: self.density.clone(),
+            band: None,
+            mistyped: 0.0,
+        }
+    }
+
+    /// Get the probability of a price in the given band, given this node.
+    fn chance(&self, band: PriceBand) -> f64 {
+        match self.peak {
+            Some(peak) => peak.chance(self.arithmetic, self.base_price, self.price_offset, band),
+            None => band_chance(self.arithmetic, self.base_price, (self.min_fac, self.max_fac),
+                                self.density.as_deref(), self.price_offset, band),
+        }
+    }
+
+    /// Narrow down the Small Spike's peak rate, on this half-day and the ones
+    /// before it that depend on it.
+    fn narrow_peak(&mut self, min: f64, max: f64) {
+        let arithmetic = self.arithmetic;
+        if let Some(peak) = self.peak.as_mut() {
+            *peak = Peak { min, max, ..*peak };
+            (self.min_fac, self.max_fac) = peak.factors(arithmetic);
+        }
+        self.history = History::narrow_peak(&self.history, arithmetic, min, max);
+    }
+
+    /// Get the nodes this one could be if a price in the given band is right,
+    /// with the chance of the price given each, when this depends on the Small
+    /// Spike's peak rate.
+    fn given_peak(&self, peak: Peak, band: PriceBand) -> Vec<(Self, f64)> {
+        if !peak.neighbour {
+            // Only the part of the peak rate that produces the price carries on.
+            let (min, max) = self.factor_range(band);
+            let mut node = self.clone();
+            node.narrow_peak(min, max);
+            return vec![(node, self.chance(band))];
+        }
+
+        // A price either side of the peak is likelier the lower the peak rate,
+        // so consider each price the peak could produce separately.
+        return peak.split(self.arithmetic, self.base_price).into_iter()
+            .map(|(part, chance)| {
+                let mut node = self.clone();
+                node.narrow_peak(part.min, part.max);
+                let chance = chance * node.chance(band);
+                (node, chance)
+            })
+            .collect();
+    }
+
+    /// Given what is known about the next price, what possible children are there?
+    pub fn children(self, price: Option<PriceBand>, options: &Options) -> Result<Vec<Self>, CalcError> {
+        let band = match price {
+            Some(band) => band,
+            None => return self.branch(None, 0.0, 1.0),
+        };
 
         // Adjust for situations where e.g. Pattern A could be in 50-100% while
         // Pattern B could be in 60-70%; if our observed price is in 60-70%,
         // then Pattern B is more likely than Pattern A given no other information.
-        // This is only applicable when we have a known price.
-        let chance = if price.is_some() {
-            1.0 / (self.max_fac - self.min_fac)
-        } else {
-            1.0
+        let given_peak = self.peak.map(|peak| self.given_peak(peak, band));
+        let right: Vec<(&Node, f64)> = match &given_peak {
+            Some(nodes) => nodes.iter().map(|(node, chance)| (node, *chance)).collect(),
+            None => vec![(&self, self.chance(band))],
         };
+        let chance: f64 = right.iter().map(|(_, chance)| chance).sum();
 
-        // If we're below the minimum length, return the next node in this phase.
-        if self.min_len > 1 {
-            return vec![self.next(price, chance)];
+        if options.error_rate == 0.0 {
+            // Prices that don't match leave no children.
+            let mut children = Vec::new();
+            for (node, chance) in right.into_iter().filter(|(_, chance)| *chance > 0.0) {
+                children.extend(node.branch(price, 0.0, chance)?);
+            }
+            return Ok(children);
         }
 
-        // If we're between min and max length, branch.
-        if self.max_len > 1 {
-            return vec![self.next(price, chance), self.after(chance)];
+        // Otherwise, the price could have been entered wrongly. It is then
+        // likeliest to be a slip away from the real price, but it is only used
+        // for the chance of the mistake, and tells us nothing else about this node.
+        let wrong_chance = options.error_rate
+            * typo::error_chance(band, |price| self.chance(PriceBand { min: price, max: price }));
+        if chance == 0.0 || (self.decrement.is_none() && self.peak.is_none()) {
+            // The price doesn't affect the children, so there's no need to
+            // consider the two cases separately. It is still kept if it fits,
+            // since it tells us what this half-day's factor probably was.
+            let price = if chance > 0.0 { price } else { None };
+            let right_chance = (1.0 - options.error_rate) * chance;
+            let mistyped = if chance > 0.0 { wrong_chance / (right_chance + wrong_chance) } else { 1.0 };
+            return self.branch(price, mistyped, right_chance + wrong_chance);
+        }
+        let mut children = Vec::new();
+        for (node, chance) in right.into_iter().filter(|(_, chance)| *chance > 0.0) {
+            children.extend(node.branch(price, 0.0, (1.0 - options.error_rate) * chance)?);
         }
+        children.extend(self.branch(None, 1.0, wrong_chance)?);
+        return Ok(children);
+    }
 
-        // If we're at max length, return the next phase.
-        return vec![self.after(chance)];
+    /// Get the children of this node, given the chance of the price, the price
+    /// itself (if known and trusted), and the chance it was mistyped.
+    fn branch(&self, price: Option<PriceBand>, mistyped: f64, chance: f64) -> Result<Vec<Self>, CalcError> {
+        let mut children = if self.min_len > 1 {
+            // If we're below the minimum length, return the next node in this phase.
+            vec![self.next(price, chance)]
+        } else if self.max_len > 1 {
+            // If we're between min and max length, branch.
+            vec![self.next(price, chance), self.after(chance)?]
+        } else {
+            // If we're at max length, return the next phase.
+            vec![self.after(chance)?]
+        };
+
+        // Remember the price on the step just taken.
+        for child in children.iter_mut() {
+            if let History::Step(step, _) = Rc::make_mut(&mut child.history) {
+                step.band = price;
+                step.mistyped = mistyped;
+            }
+        }
+        return Ok(children);
     }
 
     /// Construct a new Decreasing pattern.
-    fn decreasing(base_price: u32, prev_pattern: Option<Pattern>) -> Self {
+    fn decreasing(base_price: u32, arithmetic: Arithmetic, priors: &PatternDistribution) -> Self {
+        // The rate starts at 0.9 and then has up to 0.05 taken off.
+        let (min_fac, max_fac) = arithmetic.below(0.90, 0.05);
         Node {
             pattern: Pattern::Decreasing,
             name: "Decreasing".into(),
             base_price,
-            prob: Pattern::Decreasing.prior(prev_pattern),
+            arithmetic,
+            prob: priors.probability(Pattern::Decreasing),
             min_len: MAX_HALF_DAYS,
             max_len: MAX_HALF_DAYS,
-            min_fac: 0.85,
-            max_fac: 0.90,
+            min_fac,
+            max_fac,
             decrement: Some((0.03, 0.05)),
+            price_offset: 0,
+            peak: None,
+            density: None,
             length: 1,
             lengths: vec![],
+            history: Rc::default(),
             next_phase: TerminatorNode::new(),
         }
     }
 
     /// Construct a new Random pattern.
-    fn random(base_price: u32, prev_pattern: Option<Pattern>) -> Vec<Self> {
+    fn random(base_price: u32, arithmetic: Arithmetic, priors: &PatternDistribution) -> Vec<Self> {
+        let increasing = arithmetic.randfloat(0.90, 1.40);
+        let decreasing = arithmetic.randfloat(0.80, 0.60);
+
         let final_increasing = ConditionalLengthNode::new(Node {
             pattern: Pattern::Random,
             name: "Final Increasing".into(),
             base_price,
+            arithmetic,
             prob: 1.0,
             min_len: -1,  // Lengths will be overwritten by ConditionalLengthNode.
             max_len: -1,
-            min_fac: 0.90,
-            max_fac: 1.40,
+            min_fac: increasing.0,
+            max_fac: increasing.1,
             decrement: None,
+            price_offset: 0,
+            peak: None,
+            density: None,
             length: 1,
             lengths: vec![],
+            history: Rc::default(),
             next_phase: TerminatorNode::new(),
         }, remaining_length);
 
         // dec_1 has length 2 or 3, dec_2 has length 5 - dec_1.
-        let dec_2_length = |lengths: &Vec<i32>| {
-            let dec_1_length = *lengths.get(1).unwrap();
-            assert!(dec_1_length == 2 || dec_1_length == 3);
+        let dec_2_length = |lengths: &[i32]| {
+            let dec_1_length = *lengths.get(1)?;
+            if dec_1_length != 2 && dec_1_length != 3 {
+                return None;
+            }
             let length = 5 - dec_1_length;
-            (length, length)
+            Some((length, length))
         };
 
         let second_decreasing = ConditionalLengthNode::new(Node {
             pattern: Pattern::Random,
             name: "Second Decreasing".into(),
             base_price,
+            arithmetic,
             prob: 1.0,
             min_len: -1,
             max_len: -1,
-            min_fac: 0.60,
-            max_fac: 0.80,
+            min_fac: decreasing.0,
+            max_fac: decreasing.1,
             decrement: Some((0.04, 0.10)),
+            price_offset: 0,
+            peak: None,
+            density: None,
             length: 1,
             lengths: vec![],
+            history: Rc::default(),
             next_phase: final_increasing,
         }, dec_2_length);
 
         // inc_1 has length 0-6, inc_2 has length up to (7 - inc_1). The remainder
         // will be taken by final_inc.
-        let inc_2_length = |lengths: &Vec<i32>| {
-            let inc_1_length = *lengths.get(0).unwrap();
-            assert!(inc_1_length >= 0 && inc_1_length <= 6);
+        let inc_2_length = |lengths: &[i32]| {
+            let inc_1_length = *lengths.first()?;
+            if !(0..=6).contains(&inc_1_length) {
+                return None;
+            }
             let max_length = 7 - inc_1_length;
-            (1, max_length)
+            Some((1, max_length))
         };
 
         let second_increasing = ConditionalLengthNode::new(Node {
             pattern: Pattern::Random,
             name: "Second Increasing".into(),
             base_price,
+            arithmetic,
             prob: 1.0,
             min_len: -1,
             max_len: -1,
-            min_fac: 0.90,
-            max_fac: 1.40,
+            min_fac: increasing.0,
+            max_fac: increasing.1,
             decrement: None,
+            price_offset: 0,
+            peak: None,
+            density: None,
             length: 1,
             lengths: vec![],
+            history: Rc::default(),
             next_phase: second_decreasing,
         }, inc_2_length);
 
@@ -198,31 +764,41 @@ impl Node {
             pattern: Pattern::Random,
             name: "Initial Decreasing".into(),
             base_price,
+            arithmetic,
             prob: 1.0,
             min_len: 2,
             max_len: 3,
-            min_fac: 0.60,
-            max_fac: 0.80,
+            min_fac: decreasing.0,
+            max_fac: decreasing.1,
             decrement: Some((0.04, 0.10)),
+            price_offset: 0,
+            peak: None,
+            density: None,
             length: 1,
             lengths: vec![],
+            history: Rc::default(),
             next_phase: second_increasing,
         };
 
-        let prior = Pattern::Random.prior(prev_pattern);
+        let prior = priors.probability(Pattern::Random);
 
         let initial_increasing = Node {
             pattern: Pattern::Random,
             name: "Initial Increasing".into(),
             base_price,
+            arithmetic,
             prob: prior * 6.0 / 7.0,  // 6/7 chance for this phase to occur.
             min_len: 1,
             max_len: 6,
-            min_fac: 0.90,
-            max_fac: 1.40,
+            min_fac: increasing.0,
+            max_fac: increasing.1,
             decrement: None,
+            price_offset: 0,
+            peak: None,
+            density: None,
             length: 1,
             lengths: vec![],
+            history: Rc::default(),
             next_phase: SimpleNode::new(initial_decreasing.clone()),
         };
 
@@ -233,44 +809,73 @@ impl Node {
     }
 
     /// Construct a new Small Spike pattern.
-    fn small_spike(base_price: u32, prev_pattern: Option<Pattern>) -> Vec<Self> {
+    fn small_spike(base_price: u32, arithmetic: Arithmetic, priors: &PatternDistribution) -> Vec<Self> {
+        let decreasing = arithmetic.randfloat(0.90, 0.40);
+
         let final_decreasing = ConditionalLengthNode::new(Node {
             pattern: Pattern::SmallSpike,
             name: "Final Decreasing".into(),
             base_price,
+            arithmetic,
             prob: 1.0,
             min_len: -1,
             max_len: -1,
-            min_fac: 0.40,
-            max_fac: 0.90,
+            min_fac: decreasing.0,
+            max_fac: decreasing.1,
             decrement: Some((0.03, 0.05)),
+            price_offset: 0,
+            peak: None,
+            density: None,
             length: 1,
             lengths: vec![],
+            history: Rc::default(),
             next_phase: TerminatorNode::new(),
         }, remaining_length);
 
-        // TODO: account for weird max-rate dependencies.
+        // The peak rate is chosen first, and the prices either side of the peak
+        // are drawn below it and then have one taken off, so they are always
+        // lower than the peak. The peak rate is carried through the spike, and
+        // only split up by the price it produces once that makes a difference.
+        let (min_peak, max_peak) = arithmetic.randfloat(1.40, 2.00);
+        let peak = Peak { min: min_peak, max: max_peak, neighbour: false };
+        let neighbour = Peak { neighbour: true, ..peak };
+
+        let mut after_peak = Node::chain(Pattern::SmallSpike, "Spike", base_price, arithmetic,
+                                         final_decreasing, &[neighbour.factors(arithmetic)]);
+        after_peak.price_offset = -1;
+        after_peak.peak = Some(neighbour);
+        let mut peak_node = Node::chain(Pattern::SmallSpike, "Spike", base_price, arithmetic,
+                                        SimpleNode::new(after_peak), &[peak.factors(arithmetic)]);
+        peak_node.peak = Some(peak);
+        let mut before_peak = Node::chain(Pattern::SmallSpike, "Spike", base_price, arithmetic,
+                                          SimpleNode::new(peak_node), &[neighbour.factors(arithmetic)]);
+        before_peak.price_offset = -1;
+        before_peak.peak = Some(neighbour);
+
+        let increasing = arithmetic.randfloat(0.90, 1.40);
         let mut spike =
-            Node::chain(Pattern::SmallSpike, "Spike", base_price,
-                        final_decreasing, &vec![
-                    (0.90, 1.40), (0.90, 1.40),
-                    (1.40, 2.00), (1.40, 2.00), (1.40, 2.00)
-                ]);
+            Node::chain(Pattern::SmallSpike, "Spike", base_price, arithmetic,
+                        SimpleNode::new(before_peak), &[increasing, increasing]);
 
-        let prior = Pattern::SmallSpike.prior(prev_pattern);
+        let prior = priors.probability(Pattern::SmallSpike);
 
         let initial_decreasing = Node {
             pattern: Pattern::SmallSpike,
             name: "Initial Decreasing".into(),
             base_price,
+            arithmetic,
             prob: prior * 7.0 / 8.0,  // 7/8 chance for this phase to occur.
             min_len: 1,
             max_len: 7,
-            min_fac: 0.40,
-            max_fac: 0.90,
+            min_fac: decreasing.0,
+            max_fac: decreasing.1,
             decrement: Some((0.03, 0.05)),
+            price_offset: 0,
+            peak: None,
+            density: None,
             length: 1,
             lengths: vec![],
+            history: Rc::default(),
             next_phase: SimpleNode::new(spike.clone()),
         };
 
@@ -281,41 +886,55 @@ impl Node {
     }
 
     /// Construct a new Large Spike pattern.
-    fn large_spike(base_price: u32, prev_pattern: Option<Pattern>) -> Self {
+    fn large_spike(base_price: u32, arithmetic: Arithmetic, priors: &PatternDistribution) -> Self {
+        // Unlike the other patterns, each price after the spike is picked separately.
+        let final_rates = arithmetic.randfloat(0.40, 0.90);
+        let initial_rates = arithmetic.randfloat(0.90, 0.85);
+
         let final_decreasing = ConditionalLengthNode::new(Node {
             pattern: Pattern::LargeSpike,
             name: "Final Decreasing".into(),
             base_price,
+            arithmetic,
             prob: 1.0,
             min_len: -1,
             max_len: -1,
-            min_fac: 0.40,
-            max_fac: 0.90,
+            min_fac: final_rates.0,
+            max_fac: final_rates.1,
             decrement: None,
+            price_offset: 0,
+            peak: None,
+            density: None,
             length: 1,
             lengths: vec![],
+            history: Rc::default(),
             next_phase: TerminatorNode::new(),
         }, remaining_length);
 
         let spike = SimpleNode::new(
-            Node::chain(Pattern::LargeSpike, "Spike", base_price,
-                        final_decreasing, &vec![
+            Node::chain(Pattern::LargeSpike, "Spike", base_price, arithmetic,
+                        final_decreasing, &[
                     (0.90, 1.40), (1.40, 2.00), (2.00, 6.00),
                     (1.40, 2.00), (0.90, 1.40)
-                ]));
+                ].map(|(a, b)| arithmetic.randfloat(a, b))));
 
         let initial_decreasing = Node {
             pattern: Pattern::LargeSpike,
             name: "Initial Decreasing".into(),
             base_price,
-            prob: Pattern::LargeSpike.prior(prev_pattern),
+            arithmetic,
+            prob: priors.probability(Pattern::LargeSpike),
             min_len: 1,
             max_len: 7,
-            min_fac: 0.85,
-            max_fac: 0.90,
+            min_fac: initial_rates.0,
+            max_fac: initial_rates.1,
             decrement: Some((0.03, 0.05)),
+            price_offset: 0,
+            peak: None,
+            density: None,
             length: 1,
             lengths: vec![],
+            history: Rc::default(),
             next_phase: spike,
         };
 
@@ -325,9 +944,9 @@ impl Node {
     /// Construct a chain of nodes all with the given pattern, name, and base price.
     /// The final node in the chain will have the given `next_phase`.
     /// The factors of each node will be set according to the supplied vector.
-    fn chain(pattern: Pattern, name: &str, base_price: u32,
-             next_phase: Option<Rc<dyn NodeFactory>>, factors: &Vec<(f64, f64)>) -> Self {
-        assert!(factors.len() > 0);
+    fn chain(pattern: Pattern, name: &str, base_price: u32, arithmetic: Arithmetic,
+             next_phase: Option<Rc<dyn NodeFactory>>, factors: &[(f64, f64)]) -> Self {
+        assert!(!factors.is_empty());
 
         // Do the last node.
         let (min_fac, max_fac) = factors.last().unwrap();
@@ -335,14 +954,19 @@ impl Node {
             pattern,
             name: name.into(),
             base_price,
+            arithmetic,
             prob: 1.0,
             min_len: 1,
             max_len: 1,
             min_fac: *min_fac,
             max_fac: *max_fac,
             decrement: None,
+            price_offset: 0,
+            peak: None,
+            density: None,
             length: 1,
             lengths: vec![],
+            history: Rc::default(),
             next_phase,
         };
 
@@ -352,49 +976,64 @@ impl Node {
                 pattern,
                 name: name.into(),
                 base_price,
+                arithmetic,
                 prob: 1.0,
                 min_len: 1,
                 max_len: 1,
                 min_fac: *min_fac,
                 max_fac: *max_fac,
                 decrement: None,
+                price_offset: 0,
+                peak: None,
+                density: None,
                 length: 1,
                 lengths: vec![],
+                history: Rc::default(),
                 next_phase: SimpleNode::new(node),
             };
         }
         return node;
     }
 
-    /// Get the approximate factor of the given price compared to our base price.
-    /// Due to the rounding involved in producing the integer price from the factor
-    /// originally, we can only provide a lower and upper bound on the true factor.
-    fn factor_of(&self, price: u32) -> (f64, f64) {
-        let max = price as f64 / self.base_price as f64;
-        let min = (price as f64 - 1.0) / self.base_price as f64;
-        (min, max)
+    /// Get the distribution of our factor.
+    fn density(&self) -> Density {
+        match &self.density {
+            Some(density) => density.as_ref().clone(),
+            None => Density::uniform(self.min_fac, self.max_fac),
+        }
+    }
+
+    /// Get the part of our factor range that could produce a price in the given band.
+    fn factor_range(&self, band: PriceBand) -> (f64, f64) {
+        let (min, max) = self.arithmetic.factors_of(self.base_price, self.price_offset, band);
+        (min.max(self.min_fac), max.min(self.max_fac))
     }
 
     /// Get the next node in this current phase.
-    fn next(&self, price: Option<u32>, mut chance: f64) -> Self {
-        // Determine the factor range of the next node.
-        let (min_fac, max_fac) = match self.decrement {
+    fn next(&self, price: Option<PriceBand>, mut chance: f64) -> Self {
+        // Determine the factor range and distribution of the next node.
+        let ((min_fac, max_fac), density) = match self.decrement {
             Some((dec_min, dec_max)) => {
-                match price {
-                    Some(p) => {
-                        // We have a decrement operation and a known price.
-                        let (factor_min, factor_max) = self.factor_of(p);
-                        (factor_min - dec_max, factor_max - dec_min)
-                    }
-                    None => {
-                        // We have a decrement operation but unknown price.
-                        (self.min_fac - dec_max, self.max_fac - dec_min)
-                    }
-                }
+                let (min, max) = match price {
+                    // We have a decrement operation and a known price or
+                    // range of prices. The hidden rate must have been both
+                    // within our range and one that produces the price, so
+                    // only the part that overlaps carries on.
+                    Some(band) => self.factor_range(band),
+                    // We have a decrement operation but unknown price.
+                    None => (self.min_fac, self.max_fac),
+                };
+                let range = self.arithmetic.decrease((min, max), (dec_min, dec_max));
+                // Taking off a uniform decrement spreads the distribution out.
+                let density = self.density().window(min, max)
+                    .smear(dec_min, dec_max)
+                    .map_onto(range)
+                    .normalised();
+                (range, Some(Rc::new(density)))
             }
             None => {
                 // No decrement operation: unchanged.
-                (self.min_fac, self.max_fac)
+                ((self.min_fac, self.max_fac), self.density.clone())
             }
         };
 
@@ -409,20 +1048,25 @@ impl Node {
             pattern: self.pattern,
             name: self.name.clone(),
             base_price: self.base_price,
+            arithmetic: self.arithmetic,
             prob: self.prob * chance,
             min_len: self.min_len - 1,
             max_len: self.max_len - 1,
             min_fac,
             max_fac,
             decrement: self.decrement,
+            price_offset: self.price_offset,
+            peak: self.peak,
+            density,
             length: self.length + 1,
             lengths: self.lengths.clone(),
+            history: self.next_history(),
             next_phase: self.next_phase.clone(),
         }
     }
 
-    /// Get the node after the current phase.
-    fn after(&self, mut chance: f64) -> Self {
+    /// Get the nodes after the current phase.
+    fn after(&self, mut chance: f64) -> Result<Self, CalcError> {
         // If this is a branch, we must account for the chance of moving to the
         // next phase rather than staying with this one.
         if self.min_len <= 1 && self.max_len > 1 {
@@ -432,14 +1076,42 @@ impl Node {
 
         return self.next_phase
             .as_ref()
-            .expect("BUG: Tree terminated early!")
-            .after(&self, chance);
+            .ok_or(CalcError::Inconsistent("Tree terminated early!"))?
+            .after(self, chance);
     }
 }
 
+/// Get the chance that a factor drawn from the given range produces a price in
+/// the given band, using the factors that round up to each price. The factor is
+/// drawn uniformly unless its density is given.
+fn band_chance(arithmetic: Arithmetic, base_price: u32, (min_fac, max_fac): (f64, f64),
+               density: Option<&Density>, price_offset: i32, band: PriceBand) -> f64 {
+    let (lower, upper) = arithmetic.factors_of(base_price, price_offset, band);
+    let (lower, upper) = (lower.max(min_fac), upper.min(max_fac));
+    if upper <= lower {
+        return 0.0;
+    }
+    let share = (upper - lower) / (max_fac - min_fac);
+    let density = match density {
+        Some(density) => density,
+        None => return share,
+    };
+    // Right at the ends of the range, the density can be too small to tell
+    // apart from rounding errors, but the game can still get there, so it is
+    // never taken as impossible.
+    let total = density.mass(min_fac, max_fac);
+    if total <= 0.0 {
+        return share;
+    }
+    return (density.mass(lower, upper) / total).max(share * f64::EPSILON);
+}
+
 /// Calculate the remaining number of half-days.
-fn remaining_length(lengths: &Vec<i32>) -> (i32, i32) {
+fn remaining_length(lengths: &[i32]) -> Option<(i32, i32)> {
     let total: i32 = lengths.iter().sum();
     let remaining = MAX_HALF_DAYS - total;
-    (remaining, remaining)
+    if remaining < 0 {
+        return None;
+    }
+    Some((remaining, remaining))
 }
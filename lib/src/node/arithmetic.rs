@@ -0,0 +1,154 @@
+use crate::observation::PriceBand;
+
+/// The game rounds prices up by adding this and then truncating, so a price
+/// only just over a whole number isn't rounded up.
+const ROUND_UP: f64 = 0.99999;
+/// How far, relative to a rate, each of the game's 32-bit float operations can
+/// round it past where exact arithmetic would put it.
+const TOLERANCE: f64 = f32::EPSILON as f64 / 2.0;
+/// The largest fraction of the way between its bounds that `randfloat` can
+/// pick, since it only uses 23 random bits.
+const MAX_FRACTION: f32 = 1.0 - 1.0 / (1 << 23) as f32;
+
+/// How the game's price arithmetic is reproduced.
+/// Ranges of rates are always given as a minimum and a maximum, where the
+/// range includes its minimum but not its maximum.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Arithmetic {
+    /// Exact arithmetic, where rates can be any number in their range. The
+    /// game's rounding can take a rate just past the end of its range, so each
+    /// range is widened by as much as that rounding could move it. This accepts
+    /// every price the game can produce, and very occasionally one it can't.
+    Exact,
+    /// The game's own 32-bit floating point arithmetic, rounding after every
+    /// operation, as in the reverse-engineered code. Rates can only be 32-bit
+    /// floats, and the ends of each range are the exact floats the game can
+    /// reach, so borderline prices are accepted or rejected as the game would
+    /// produce them.
+    Float32,
+}
+
+impl Arithmetic {
+    /// Choose the arithmetic to use.
+    pub fn new(float32: bool) -> Self {
+        if float32 {
+            Arithmetic::Float32
+        } else {
+            Arithmetic::Exact
+        }
+    }
+
+    /// Get the range of rates that the game's `randfloat(a, b)` can pick.
+    /// Either bound may be the larger one.
+    pub fn randfloat(self, a: f64, b: f64) -> (f64, f64) {
+        match self {
+            Arithmetic::Exact => {
+                // `a` itself can be picked, and rounded either way, but `b` can't.
+                let slack = a.abs() * TOLERANCE;
+                if a <= b { (a - slack, b) } else { (b, a + slack) }
+            }
+            Arithmetic::Float32 => {
+                let (a, b) = (a as f32, b as f32);
+                let pick = |fraction: f32| a + fraction * (b - a);
+                let (first, last) = (pick(0.0), pick(MAX_FRACTION));
+                let (min, max) = if first <= last { (first, last) } else { (last, first) };
+                (min as f64, max.next_up() as f64)
+            }
+        }
+    }
+
+    /// Get the range of rates that `randfloat(a, rate)` can pick for any rate
+    /// in the given range, all of which are above `a`.
+    pub fn randfloat_to(self, a: f64, (_, max): (f64, f64)) -> (f64, f64) {
+        match self {
+            Arithmetic::Exact => (a - a.abs() * TOLERANCE, max),
+            Arithmetic::Float32 => self.randfloat(a, (max as f32).next_down() as f64),
+        }
+    }
+
+    /// Get the range of rates from `rate = start; rate -= randfloat(0, width);`.
+    pub fn below(self, start: f64, width: f64) -> (f64, f64) {
+        match self {
+            Arithmetic::Exact => widen((start - width, start)),
+            Arithmetic::Float32 => {
+                let start = start as f32;
+                ((start - MAX_FRACTION * width as f32) as f64, start.next_up() as f64)
+            }
+        }
+    }
+
+    /// Get the range of rates after taking off a decrement between `dec_min` and
+    /// `dec_max`, which the game does with `rate -= dec_min;` and then
+    /// `rate -= randfloat(0, dec_max - dec_min);`.
+    pub fn decrease(self, (min, max): (f64, f64), (dec_min, dec_max): (f64, f64)) -> (f64, f64) {
+        match self {
+            Arithmetic::Exact => widen((min - dec_max, max - dec_min)),
+            Arithmetic::Float32 => {
+                // The fixed part is a double in the game's code, so it is taken
+                // off in double precision before rounding back to a float.
+                let most = MAX_FRACTION * (dec_max - dec_min) as f32;
+                let step = |rate: f32, taken: f32| (rate as f64 - dec_min) as f32 - taken;
+                let min = step(min as f32, most);
+                let max = step((max as f32).next_down(), 0.0);
+                (min as f64, max.next_up() as f64)
+            }
+        }
+    }
+
+    /// Get the range of rates that `decrease` could have turned into a rate in
+    /// the given range.
+    pub fn undo_decrease(self, (min, max): (f64, f64), (dec_min, dec_max): (f64, f64)) -> (f64, f64) {
+        match self {
+            Arithmetic::Exact => widen((min + dec_min, max + dec_max)),
+            Arithmetic::Float32 => {
+                let most = MAX_FRACTION * (dec_max - dec_min) as f32;
+                let step = |rate: f32, taken: f32| ((rate as f64 - dec_min) as f32 - taken) as f64;
+                // The smallest rate that can still reach the range, and the
+                // smallest that can no longer get below it.
+                (first_float(min + dec_min, |rate| step(rate, 0.0) >= min),
+                 first_float(max + dec_max, |rate| step(rate, most) >= max))
+            }
+        }
+    }
+
+    /// Get the range of rates that produce a price in the given band, once
+    /// multiplied by the base price, rounded up and offset.
+    pub fn factors_of(self, base_price: u32, price_offset: i32, band: PriceBand) -> (f64, f64) {
+        // Undo any offset to get the prices as they were rounded.
+        let min = band.min as f64 - price_offset as f64;
+        let max = band.max as f64 - price_offset as f64;
+        (self.first_rate(base_price, min), self.first_rate(base_price, max + 1.0))
+    }
+
+    /// Get the smallest rate that rounds up to at least the given price.
+    fn first_rate(self, base_price: u32, price: f64) -> f64 {
+        let exact = (price - ROUND_UP) / base_price as f64;
+        if self == Arithmetic::Exact || price <= 0.0 {
+            return exact;
+        }
+
+        // The game's `intceil(rate * basePrice)`.
+        let base_price = base_price as f32;
+        let produces = |rate: f32| (rate * base_price + ROUND_UP as f32) as i64 as f64;
+        return first_float(exact, |rate| produces(rate) >= price);
+    }
+}
+
+/// Widen a range of rates by as much as the game's rounding could move each end.
+fn widen((min, max): (f64, f64)) -> (f64, f64) {
+    return (min - min.abs() * TOLERANCE, max + max.abs() * TOLERANCE);
+}
+
+/// Find the smallest float for which the given test passes, where the test
+/// passes for every float above one that passes. The float nearest the exact
+/// answer, which is given, must be at most a few floats out.
+fn first_float(exact: f64, passes: impl Fn(f32) -> bool) -> f64 {
+    let mut rate = exact as f32;
+    while passes(rate) {
+        rate = rate.next_down();
+    }
+    while !passes(rate) {
+        rate = rate.next_up();
+    }
+    return rate as f64;
+}
@@ -0,0 +1,310 @@
+use std::hash::{Hash, Hasher};
+
+/// Breakpoints closer together than this are treated as the same. The game picks
+/// its rates as 32-bit floats, so rates any closer than this can't be told apart.
+const BREAKPOINT_EPSILON: f64 = f32::EPSILON as f64;
+
+/// A function of a hidden rate, made up of a polynomial on each of several
+/// consecutive ranges, and zero outside them.
+/// This is mostly used for the distribution of a rate, which starts out
+/// uniform but, after each decrement, is the sum of several uniform draws: a
+/// trapezoid after one, and then pieces of ever higher degree.
+#[derive(Clone, Debug, Default)]
+pub struct Density {
+    /// The pieces, in ascending order, each starting where the last ended.
+    pieces: Vec<Piece>,
+}
+
+/// A polynomial on a single range of rates.
+#[derive(Clone, Debug)]
+struct Piece {
+    /// The lowest rate in the range.
+    start: f64,
+    /// The rate just above the highest in the range.
+    end: f64,
+    /// The coefficients of the polynomial in `rate - start`, lowest power first.
+    coefs: Vec<f64>,
+}
+
+impl Piece {
+    /// Get the integral from the start of the piece to the given rate.
+    fn integral(&self, rate: f64) -> f64 {
+        let u = rate - self.start;
+        self.coefs.iter().enumerate().rev()
+            .fold(0.0, |value, (power, coef)| value * u + coef / (power + 1) as f64) * u
+    }
+
+    /// Get the integral of the rate times the polynomial, from the start of the
+    /// piece to the given rate.
+    fn moment(&self, rate: f64) -> f64 {
+        let u = rate - self.start;
+        let about_start = self.coefs.iter().enumerate().rev()
+            .fold(0.0, |value, (power, coef)| value * u + coef / (power + 2) as f64) * u * u;
+        return self.start * self.integral(rate) + about_start;
+    }
+
+    /// Get the coefficients of the antiderivative that is `constant` at the
+    /// start of the piece.
+    fn antiderivative(&self, constant: f64) -> Vec<f64> {
+        let mut coefs = Vec::with_capacity(self.coefs.len() + 1);
+        coefs.push(constant);
+        coefs.extend(self.coefs.iter().enumerate().map(|(power, coef)| coef / (power + 1) as f64));
+        return coefs;
+    }
+}
+
+impl Density {
+    /// A rate drawn uniformly from the given range.
+    pub fn uniform(min: f64, max: f64) -> Self {
+        return Density::constant(min, max, 1.0 / (max - min));
+    }
+
+    /// A function that is the given value across the given range.
+    pub fn constant(min: f64, max: f64, value: f64) -> Self {
+        return Density { pieces: vec![Piece { start: min, end: max, coefs: vec![value] }] };
+    }
+
+    /// Get the integral across the given range of rates.
+    pub fn mass(&self, min: f64, max: f64) -> f64 {
+        let mut mass = 0.0;
+        for piece in self.pieces.iter() {
+            let (start, end) = (min.max(piece.start), max.min(piece.end));
+            if end > start {
+                mass += piece.integral(end) - piece.integral(start);
+            }
+        }
+        return mass;
+    }
+
+    /// Get the mean rate across the given range, treating this as a distribution,
+    /// or `None` if it is zero throughout the range.
+    pub fn mean(&self, min: f64, max: f64) -> Option<f64> {
+        let (mut mass, mut moment) = (0.0, 0.0);
+        for piece in self.pieces.iter() {
+            let (start, end) = (min.max(piece.start), max.min(piece.end));
+            if end > start {
+                mass += piece.integral(end) - piece.integral(start);
+                moment += piece.moment(end) - piece.moment(start);
+            }
+        }
+        return if mass > 0.0 { Some(moment / mass) } else { None };
+    }
+
+    /// Get the integral across every rate.
+    pub fn total(&self) -> f64 {
+        self.pieces.iter().map(|piece| piece.integral(piece.end)).sum()
+    }
+
+    /// Get the same function, scaled to have an integral of 1 so that it is a
+    /// distribution. A function that is zero everywhere is left alone.
+    pub fn normalised(mut self) -> Self {
+        let total = self.total();
+        if total > 0.0 {
+            for piece in self.pieces.iter_mut() {
+                piece.coefs.iter_mut().for_each(|coef| *coef /= total);
+            }
+        }
+        return self;
+    }
+
+    /// Get the same function, but zero outside the given range.
+    pub fn window(&self, min: f64, max: f64) -> Self {
+        let mut pieces = Vec::new();
+        for piece in self.pieces.iter() {
+            let (start, end) = (min.max(piece.start), max.min(piece.end));
+            if end > start {
+                pieces.push(Piece { start, end, coefs: shift(&piece.coefs, start - piece.start) });
+            }
+        }
+        return Density { pieces };
+    }
+
+    /// Get the average of this function over the rates from `a` to `b` above
+    /// each rate, i.e. `g(y) = ∫ f(y + x) dx / (b - a)` for `x` from `a` to `b`.
+    /// If this is the distribution of a rate, and `x` is drawn uniformly from
+    /// the range, this is the distribution of the rate with `x` taken off.
+    pub fn smear(&self, a: f64, b: f64) -> Self {
+        let width = b - a;
+        // The integral up to the start of each piece.
+        let mut before = Vec::with_capacity(self.pieces.len());
+        let mut total = 0.0;
+        for piece in self.pieces.iter() {
+            before.push(total);
+            total += piece.integral(piece.end);
+        }
+
+        // The integral up to the given rate, as a polynomial in the distance
+        // from `from`, which holds until the rate reaches another piece.
+        // The rates only ever go up, so the piece they are in is kept track of.
+        let integral = |piece: &mut usize, from: f64, mid: f64| -> Vec<f64> {
+            if mid < self.min() {
+                return vec![0.0];
+            }
+            while *piece < self.pieces.len() && self.pieces[*piece].end <= mid {
+                *piece += 1;
+            }
+            match self.pieces.get(*piece) {
+                Some(found) if found.start <= mid => shift(&found.antiderivative(before[*piece]), from - found.start),
+                _ => vec![total],
+            }
+        };
+
+        let mut breakpoints: Vec<f64> = self.pieces.iter()
+            .flat_map(|piece| [piece.start, piece.end])
+            .flat_map(|rate| [rate - a, rate - b])
+            .collect();
+        breakpoints.sort_by(f64::total_cmp);
+        breakpoints.dedup_by(|later, earlier| *later - *earlier < BREAKPOINT_EPSILON);
+
+        let mut pieces = Vec::with_capacity(breakpoints.len());
+        let (mut upper_piece, mut lower_piece) = (0, 0);
+        for pair in breakpoints.windows(2) {
+            let (start, end) = (pair[0], pair[1]);
+            let mid = (start + end) / 2.0;
+            let upper = integral(&mut upper_piece, start + b, mid + b);
+            let lower = integral(&mut lower_piece, start + a, mid + a);
+            let len = upper.len().max(lower.len());
+            let coefs = (0..len)
+                .map(|i| (upper.get(i).unwrap_or(&0.0) - lower.get(i).unwrap_or(&0.0)) / width)
+                .collect();
+            pieces.push(Piece { start, end, coefs });
+        }
+        return Density { pieces };
+    }
+
+    /// Stretch this function onto the given range, keeping its integral.
+    /// The game's rounding can move the ends of a range slightly from where
+    /// exact arithmetic puts them, so this keeps the function to the range
+    /// the game can really reach.
+    pub fn map_onto(&self, (min, max): (f64, f64)) -> Self {
+        let (from_min, from_max) = (self.min(), self.max());
+        if from_max <= from_min || max <= min {
+            return self.clone();
+        }
+        let scale = (max - min) / (from_max - from_min);
+        let map = |rate: f64| min + (rate - from_min) * scale;
+        let pieces = self.pieces.iter().map(|piece| {
+            let mut factor = 1.0;
+            let coefs = piece.coefs.iter().map(|coef| {
+                factor /= scale;
+                coef * factor
+            }).collect();
+            Piece { start: map(piece.start), end: map(piece.end), coefs }
+        }).collect();
+        return Density { pieces };
+    }
+
+    /// Get the product of this function and another.
+    pub fn product(&self, other: &Density) -> Self {
+        let (min, max) = (self.min().max(other.min()), self.max().min(other.max()));
+        return self.combine(other, (min, max), |a, b| {
+            let mut coefs = vec![0.0; a.len() + b.len() - 1];
+            for (i, x) in a.iter().enumerate() {
+                for (j, y) in b.iter().enumerate() {
+                    coefs[i + j] += x * y;
+                }
+            }
+            coefs
+        });
+    }
+
+    /// Get the sum of this function and another multiplied by `weight`.
+    pub fn plus(&self, other: &Density, weight: f64) -> Self {
+        let (min, max) = match (self.pieces.is_empty(), other.pieces.is_empty()) {
+            (true, _) => (other.min(), other.max()),
+            (_, true) => (self.min(), self.max()),
+            _ => (self.min().min(other.min()), self.max().max(other.max())),
+        };
+        return self.combine(other, (min, max), |a, b| {
+            (0..a.len().max(b.len()))
+                .map(|i| a.get(i).unwrap_or(&0.0) + b.get(i).unwrap_or(&0.0) * weight)
+                .collect()
+        });
+    }
+
+    /// Combine this function with another across the given range, piece by
+    /// piece, given a way to combine the polynomials of each.
+    fn combine<F>(&self, other: &Density, (min, max): (f64, f64), f: F) -> Self
+        where F: Fn(&[f64], &[f64]) -> Vec<f64>
+    {
+        if max <= min {
+            return Density { pieces: Vec::new() };
+        }
+        let mut breakpoints: Vec<f64> = self.pieces.iter().chain(other.pieces.iter())
+            .flat_map(|piece| [piece.start, piece.end])
+            .filter(|rate| min <= *rate && *rate <= max)
+            .chain([min, max])
+            .collect();
+        breakpoints.sort_by(f64::total_cmp);
+        breakpoints.dedup_by(|later, earlier| *later - *earlier < BREAKPOINT_EPSILON);
+
+        // Get the polynomial of a function that holds around `mid`, in the
+        // distance from `from`. The rates only ever go up, so the piece they
+        // are in is kept track of.
+        let poly = |density: &Density, piece: &mut usize, from: f64, mid: f64| -> Vec<f64> {
+            while *piece < density.pieces.len() && density.pieces[*piece].end <= mid {
+                *piece += 1;
+            }
+            match density.pieces.get(*piece) {
+                Some(found) if found.start <= mid => shift(&found.coefs, from - found.start),
+                _ => vec![0.0],
+            }
+        };
+
+        let mut pieces = Vec::with_capacity(breakpoints.len());
+        let (mut own_piece, mut other_piece) = (0, 0);
+        for pair in breakpoints.windows(2).filter(|pair| pair[1] > pair[0]) {
+            let (start, end) = (pair[0], pair[1]);
+            let mid = (start + end) / 2.0;
+            let coefs = f(&poly(self, &mut own_piece, start, mid), &poly(other, &mut other_piece, start, mid));
+            pieces.push(Piece { start, end, coefs });
+        }
+        return Density { pieces };
+    }
+
+    /// The lowest rate where this can be non-zero.
+    pub fn min(&self) -> f64 {
+        self.pieces.first().map_or(0.0, |piece| piece.start)
+    }
+
+    /// The rate just above the highest where this can be non-zero.
+    pub fn max(&self) -> f64 {
+        self.pieces.last().map_or(0.0, |piece| piece.end)
+    }
+
+    /// Every number that makes up the function, by their bits.
+    fn bits(&self) -> impl Iterator<Item = u64> + '_ {
+        self.pieces.iter().flat_map(|piece| {
+            [piece.start.to_bits(), piece.end.to_bits(), piece.coefs.len() as u64].into_iter()
+                .chain(piece.coefs.iter().map(|coef| coef.to_bits()))
+        })
+    }
+}
+
+impl PartialEq for Density {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits().eq(other.bits())
+    }
+}
+
+impl Eq for Density {}
+
+impl Hash for Density {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for bits in self.bits() {
+            bits.hash(state);
+        }
+    }
+}
+
+/// Get the coefficients of `p(u + by)`, given those of `p(u)`, lowest power first.
+fn shift(coefs: &[f64], by: f64) -> Vec<f64> {
+    let mut coefs = coefs.to_vec();
+    let len = coefs.len();
+    for i in 0..len {
+        for j in (i..len - 1).rev() {
+            coefs[j] += by * coefs[j + 1];
+        }
+    }
+    return coefs;
+}
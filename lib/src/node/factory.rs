@@ -1,13 +1,15 @@
 use std::rc::Rc;
 
+use crate::error::CalcError;
 use super::Node;
 
 /// This allows us to swap in different methods for constructing the following phase.
 pub trait NodeFactory {
-    fn after(&self, prev: &Node, chance: f64) -> Node;
+    fn after(&self, prev: &Node, chance: f64) -> Result<Node, CalcError>;
 }
 
-/// The simplest NodeFactory, which only passes on the probability and previous lengths.
+/// The simplest NodeFactory, which only passes on the probability, previous lengths,
+/// history and peak rate.
 pub struct SimpleNode {
     after: Node,
 }
@@ -18,27 +20,39 @@ impl SimpleNode {
     }
 }
 
-impl NodeFactory for SimpleNode {
-    fn after(&self, prev: &Node, chance: f64) -> Node {
+impl SimpleNode {
+    fn next(&self, prev: &Node, chance: f64) -> Node {
         let mut after = self.after.clone();
 
         after.prob *= prev.prob * chance;
         after.lengths = prev.lengths.clone();
         after.lengths.push(prev.length);
+        after.history = prev.next_history();
+        // Carry on with however much of the peak rate is still possible.
+        if let (Some(_), Some(peak)) = (after.peak, prev.peak) {
+            after.narrow_peak(peak.min, peak.max);
+        }
 
         return after;
     }
 }
 
+impl NodeFactory for SimpleNode {
+    fn after(&self, prev: &Node, chance: f64) -> Result<Node, CalcError> {
+        return Ok(self.next(prev, chance));
+    }
+}
+
 /// A NodeFactory which additionally sets the phase lengths from previous
 /// phase lengths via an arbitrary function.
+/// If the function returns `None`, the previous lengths were impossible.
 pub struct ConditionalLengthNode<F> {
     base: SimpleNode,
     length_func: F,
 }
 
 impl<F: 'static> ConditionalLengthNode<F>
-    where F: Fn(&Vec<i32>) -> (i32, i32)
+    where F: Fn(&[i32]) -> Option<(i32, i32)>
 {
     pub fn new(after: Node, length_func: F) -> Option<Rc<dyn NodeFactory>> {
         Some(Rc::new(Self {
@@ -49,16 +63,17 @@ impl<F: 'static> ConditionalLengthNode<F>
 }
 
 impl<F> NodeFactory for ConditionalLengthNode<F>
-    where F: Fn(&Vec<i32>) -> (i32, i32)
+    where F: Fn(&[i32]) -> Option<(i32, i32)>
 {
-    fn after(&self, prev: &Node, chance: f64) -> Node {
-        let mut after = self.base.after(prev, chance);
+    fn after(&self, prev: &Node, chance: f64) -> Result<Node, CalcError> {
+        let mut after = self.base.next(prev, chance);
 
-        let (min_len, max_len) = (self.length_func)(&after.lengths);
+        let (min_len, max_len) = (self.length_func)(&after.lengths)
+            .ok_or(CalcError::Inconsistent("Impossible phase lengths!"))?;
         after.min_len = min_len;
         after.max_len = max_len;
 
-        return after;
+        return Ok(after);
     }
 }
 
@@ -66,7 +81,7 @@ impl<F> NodeFactory for ConditionalLengthNode<F>
 /// It's useful to have a dummy node here, since the `after` is constructed when
 /// the final real node is considered, so if it was `None`, we would panic.
 /// If we go too far and try to process a TerminatorNode, we will hit a `None`
-/// and correctly panic.
+/// and correctly return an error.
 pub struct TerminatorNode;
 
 impl TerminatorNode {
@@ -76,19 +91,24 @@ impl TerminatorNode {
 }
 
 impl NodeFactory for TerminatorNode {
-    fn after(&self, prev: &Node, chance: f64) -> Node {
+    fn after(&self, prev: &Node, chance: f64) -> Result<Node, CalcError> {
         let after = Node {
             pattern: prev.pattern,
             name: "Terminator".to_string(),
             base_price: prev.base_price,
+            arithmetic: prev.arithmetic,
             prob: 1.0,
             min_len: 0,
             max_len: 0,
             min_fac: 0.0,
             max_fac: 0.0,
             decrement: None,
+            price_offset: 0,
+            peak: None,
+            density: None,
             length: 0,
             lengths: vec![],
+            history: Rc::default(),
             next_phase: None
         };
 
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::{Density, History, Node, Step};
+
+/// A half-day of the ways some nodes could have got where they are, as it looks
+/// given every price rather than only the prices before it.
+pub struct Smoothed<'a> {
+    /// The index of the half-day, where 0 is Monday morning.
+    pub half_day: usize,
+    /// The step as it was taken, given only the prices before it.
+    pub step: &'a Step,
+    /// The step given every price. Within a decreasing phase, the prices after
+    /// a half-day make some of its factors likelier than others, so both the
+    /// range and the distribution of its factor take account of them.
+    /// The distribution still leaves out the half-day's own price.
+    pub smoothed: Step,
+    /// The probability of taking this step, out of every way of getting to the
+    /// nodes.
+    pub prob: f64,
+}
+
+/// How likely the prices from a half-day onwards in its phase are, for each
+/// factor the half-day could have, up to a constant factor.
+#[derive(Clone)]
+struct Likelihood {
+    /// The phase of the prices this is for.
+    phase: usize,
+    density: Density,
+    /// How likely the prices are overall, given the distribution of the factor
+    /// before them, with the same constant factor.
+    chance: f64,
+}
+
+/// What is gathered about one part of the histories while working backwards.
+struct Entry<'a> {
+    history: &'a History,
+    /// The index of the node this part of the history leads to, i.e. the number
+    /// of steps before it.
+    length: usize,
+    /// The number of later parts of the histories that lead here and are yet
+    /// to be worked through.
+    waiting: usize,
+    /// The probability of the histories through here.
+    prob: f64,
+    /// What each later part of the histories that leads here says about the
+    /// factor of this node, with the probability of the histories through it.
+    likelihoods: Vec<(f64, Option<Likelihood>)>,
+}
+
+/// Get every step of every way the given nodes could have got where they are,
+/// as it looks given every price on the way.
+///
+/// Merged nodes share the steps after they were merged, so rather than going
+/// through every way separately, this works backwards through the shared steps
+/// once, mixing together what the later steps say about each one.
+pub fn smooth(nodes: &[Node]) -> Vec<Smoothed<'_>> {
+    let total: f64 = nodes.iter().map(|node| node.prob).sum();
+    let key = |history: &History| history as *const History;
+
+    // Find every part of the histories, and how many later parts lead to each.
+    let mut entries: HashMap<*const History, Entry> = HashMap::new();
+    let mut stack: Vec<(&History, usize)> = Vec::new();
+    for node in nodes {
+        let (history, length) = (node.history.as_ref(), node.path().len());
+        let entry = entries.entry(key(history)).or_insert_with(|| {
+            stack.push((history, length));
+            Entry { history, length, waiting: 0, prob: 0.0, likelihoods: Vec::new() }
+        });
+        entry.prob += node.prob / total;
+        entry.likelihoods.push((node.prob / total, None));
+    }
+    while let Some((history, length)) = stack.pop() {
+        for (earlier, length) in history.earlier(length) {
+            entries.entry(key(earlier)).or_insert_with(|| {
+                stack.push((earlier, length));
+                Entry { history: earlier, length, waiting: 0, prob: 0.0, likelihoods: Vec::new() }
+            }).waiting += 1;
+        }
+    }
+
+    // Work backwards, only moving on to each part once everything after it is done.
+    let mut ready: Vec<*const History> = entries.iter()
+        .filter(|(_, entry)| entry.waiting == 0)
+        .map(|(key, _)| *key)
+        .collect();
+    let mut results = Vec::new();
+    while let Some(current) = ready.pop() {
+        let entry = entries.remove(&current).expect("BUG: Missing history!");
+        let likelihood = match entry.history.continuing() {
+            Some(step) => mix(entry.likelihoods, step.next_rates()),
+            None => None,
+        };
+
+        let mut earlier: Vec<(&History, f64, Option<Likelihood>)> = Vec::new();
+        match entry.history {
+            History::Start => {}
+            History::Step(step, prev) => {
+                // The prices after this half-day in the same phase tell us about
+                // its factor, once the decrement is undone.
+                let later = match (likelihood, step.decrement) {
+                    (Some(likelihood), Some((dec_min, dec_max))) if likelihood.phase == step.phase => {
+                        let density = likelihood.density;
+                        let range = step.arithmetic.undo_decrease((density.min(), density.max()), (dec_min, dec_max));
+                        Some(density.smear(-dec_max, -dec_min).map_onto(range))
+                    }
+                    _ => None,
+                };
+                let (min, max) = step.rates();
+                let mut smoothed = step.clone();
+                (smoothed.min_fac, smoothed.max_fac) = (min, max);
+                let chance = match &later {
+                    Some(later) => {
+                        let density = step.density().product(later);
+                        let chance = density.mass(min, max);
+                        if density.total() > 0.0 {
+                            smoothed.density = Some(Rc::new(density.normalised()));
+                        }
+                        if later.max() > min.max(later.min()) && later.min() < max.min(later.max()) {
+                            (smoothed.min_fac, smoothed.max_fac) = (min.max(later.min()), max.min(later.max()));
+                        }
+                        chance
+                    }
+                    None => step.density().mass(min, max),
+                };
+
+                // The half-day before only needs to know about this one if it
+                // is in the same phase and its factor carries on to here.
+                let own = match prev.continuing() {
+                    Some(before) if before.phase == step.phase => Some(Likelihood {
+                        phase: step.phase,
+                        density: match later {
+                            Some(later) => later.window(min, max),
+                            None => Density::constant(min, max, 1.0),
+                        },
+                        chance,
+                    }),
+                    _ => None,
+                };
+                earlier.push((prev.as_ref(), entry.prob, own));
+                results.push(Smoothed { half_day: entry.length - 1, step, smoothed, prob: entry.prob });
+            }
+            History::Merged(histories) => {
+                for (weight, history) in histories {
+                    earlier.push((history.as_ref(), entry.prob * weight, likelihood.clone()));
+                }
+            }
+        }
+
+        for (history, prob, likelihood) in earlier {
+            let entry = entries.get_mut(&key(history)).expect("BUG: Missing history!");
+            entry.prob += prob;
+            entry.likelihoods.push((prob, likelihood));
+            entry.waiting -= 1;
+            if entry.waiting == 0 {
+                ready.push(key(history));
+            }
+        }
+    }
+    return results;
+}
+
+/// Mix together what each later part of the histories says about a node's
+/// factor, given the range of factors the node could have.
+/// Each is weighted by the probability of the histories through it, relative
+/// to how likely it makes the prices, so that together they give the right
+/// distribution for the factor given every history.
+fn mix(likelihoods: Vec<(f64, Option<Likelihood>)>, (min, max): (f64, f64)) -> Option<Likelihood> {
+    let phase = likelihoods.iter().find_map(|(_, likelihood)| likelihood.as_ref())?.phase;
+    if likelihoods.len() == 1 {
+        return likelihoods.into_iter().next().and_then(|(_, likelihood)| likelihood);
+    }
+
+    let mut mixed = Likelihood { phase, density: Density::default(), chance: 0.0 };
+    for (prob, likelihood) in likelihoods {
+        // Later steps that say nothing about the factor allow all of it.
+        let likelihood = likelihood.unwrap_or_else(|| Likelihood {
+            phase,
+            density: Density::constant(min, max, 1.0),
+            chance: 1.0,
+        });
+        if prob > 0.0 && likelihood.chance > 0.0 {
+            mixed.density = mixed.density.plus(&likelihood.density, prob / likelihood.chance);
+            mixed.chance += prob;
+        }
+    }
+    return Some(mixed);
+}
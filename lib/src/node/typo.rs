@@ -0,0 +1,90 @@
+use crate::observation::PriceBand;
+
+/// The highest price we expect to be entered, even by mistake. Real prices have
+/// at most three digits, so this allows for one extra digit.
+const MAX_TYPED_PRICE: u32 = 9999;
+/// The share of mistaken prices that are a slip of a single key, rather than
+/// anything at all, e.g. a price from another day or another island.
+const SLIP_SHARE: f64 = 0.8;
+
+/// Get the chance of a price in the given band being entered by mistake, given
+/// the chance of each real price.
+///
+/// Most mistakes are a slip of a single key: a price one too high or low, one
+/// digit wrong, or two neighbouring digits swapped. Each kind of slip is equally
+/// likely, as is each way of making it. The rest of the mistakes could be any
+/// price that could be typed, so even a price far beyond what the game produces
+/// keeps some chance, but one that is too high to be typed at all has none.
+/// A range or comparison isn't typed digit by digit, so a mistake in one could
+/// be anything.
+pub fn error_chance(band: PriceBand, chance: impl Fn(u32) -> f64) -> f64 {
+    if band.min > MAX_TYPED_PRICE {
+        return 0.0;
+    }
+    let min = band.min.min(MAX_TYPED_PRICE);
+    let max = band.max.min(MAX_TYPED_PRICE);
+    let anything = (max - min + 1) as f64 / (MAX_TYPED_PRICE + 1) as f64;
+    if band.min != band.max {
+        return anything;
+    }
+
+    // Every slip can be undone by a slip of the same kind, so the real price
+    // must be one slip away from the typed one.
+    let typed = band.min;
+    let mut reals: Vec<u32> = slips(typed).concat();
+    reals.sort_unstable();
+    reals.dedup();
+    let slip: f64 = reals.into_iter()
+        .map(|real| (real, chance(real)))
+        .filter(|(_, chance)| *chance > 0.0)
+        .map(|(real, chance)| slip_chance(real, typed) * chance)
+        .sum();
+    return (1.0 - SLIP_SHARE) * anything + SLIP_SHARE * slip;
+}
+
+/// Get the chance that a slip of a single key turns one price into another.
+fn slip_chance(real: u32, typed: u32) -> f64 {
+    let slips = slips(real);
+    let kinds = slips.iter().filter(|prices| !prices.is_empty()).count();
+    return slips.iter()
+        .filter(|prices| prices.contains(&typed))
+        .map(|prices| 1.0 / (kinds * prices.len()) as f64)
+        .sum();
+}
+
+/// Get every price that a slip of a single key could turn the given price into,
+/// for each kind of slip: one too high or low, one digit wrong, and two
+/// neighbouring digits swapped.
+fn slips(price: u32) -> [Vec<u32>; 3] {
+    let digits: Vec<u8> = price.to_string().into_bytes();
+    let number = |digits: &[u8]| -> Option<u32> {
+        // A leading zero would just be left off.
+        if digits.len() > 1 && digits[0] == b'0' {
+            return None;
+        }
+        std::str::from_utf8(digits).ok()?.parse().ok()
+    };
+
+    let off_by_one: Vec<u32> = [price.checked_sub(1), price.checked_add(1)].into_iter().flatten().collect();
+    let mut wrong_digit = Vec::new();
+    for i in 0..digits.len() {
+        for digit in b'0'..=b'9' {
+            let mut changed = digits.clone();
+            changed[i] = digit;
+            wrong_digit.extend(number(&changed));
+        }
+    }
+    let mut swapped = Vec::new();
+    for i in 1..digits.len() {
+        let mut changed = digits.clone();
+        changed.swap(i - 1, i);
+        swapped.extend(number(&changed));
+    }
+
+    return [off_by_one, wrong_digit, swapped].map(|mut prices| {
+        prices.retain(|typed| *typed != price && *typed <= MAX_TYPED_PRICE);
+        prices.sort_unstable();
+        prices.dedup();
+        prices
+    });
+}
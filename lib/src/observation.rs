@@ -0,0 +1,122 @@
+use std::fmt::{Display, Formatter};
+
+/// The range of prices that could appear on a single half-day, inclusive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriceBand {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl PriceBand {
+    /// Widen this band to include another.
+    pub(crate) fn merge(&mut self, other: PriceBand) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+impl Display for PriceBand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else if self.max == u32::MAX {
+            write!(f, "{}+", self.min)
+        } else if self.min == 0 {
+            write!(f, "{} or less", self.max)
+        } else {
+            write!(f, "{}-{}", self.min, self.max)
+        }
+    }
+}
+
+/// What is known about the price on a single half-day.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Observation {
+    /// The price was missed.
+    Unknown,
+    /// The price is known exactly.
+    Exact(u32),
+    /// The price is between the two given prices, inclusive.
+    Between(u32, u32),
+    /// The price is the given price or higher.
+    AtLeast(u32),
+    /// The price is the given price or lower.
+    AtMost(u32),
+    /// The price is lower than on the previous half-day.
+    LowerThanPrevious,
+    /// The price is higher than on the previous half-day.
+    HigherThanPrevious,
+}
+
+impl From<Option<u32>> for Observation {
+    fn from(price: Option<u32>) -> Self {
+        match price {
+            Some(p) => Observation::Exact(p),
+            None => Observation::Unknown,
+        }
+    }
+}
+
+impl From<u32> for Observation {
+    fn from(price: u32) -> Self {
+        Observation::Exact(price)
+    }
+}
+
+impl Display for Observation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Observation::Unknown => write!(f, "?"),
+            Observation::Exact(p) => write!(f, "{}", p),
+            Observation::Between(a, b) => write!(f, "{}-{}", a, b),
+            Observation::AtLeast(p) => write!(f, "{}+", p),
+            Observation::AtMost(p) => write!(f, "{}-", p),
+            Observation::LowerThanPrevious => write!(f, "lower"),
+            Observation::HigherThanPrevious => write!(f, "higher"),
+        }
+    }
+}
+
+impl Observation {
+    /// Work out the range of prices allowed on each half-day, or `None` if
+    /// nothing is known.
+    /// Comparisons with the previous half-day can only use what is known about
+    /// that half-day, so e.g. "lower than an unknown price" tells us nothing.
+    pub(crate) fn bands(observations: &[Observation]) -> Vec<Option<PriceBand>> {
+        let mut bands: Vec<Option<PriceBand>> = Vec::with_capacity(observations.len());
+        for observation in observations {
+            let previous = bands.last().copied().flatten();
+            let band = match (observation, previous) {
+                (Observation::Unknown, _) => None,
+                (Observation::Exact(p), _) => Some(PriceBand { min: *p, max: *p }),
+                (Observation::Between(a, b), _) => Some(PriceBand { min: *a.min(b), max: *a.max(b) }),
+                (Observation::AtLeast(p), _) => Some(PriceBand { min: *p, max: u32::MAX }),
+                (Observation::AtMost(p), _) => Some(PriceBand { min: 0, max: *p }),
+                (Observation::LowerThanPrevious, Some(prev)) => {
+                    Some(PriceBand { min: 0, max: prev.max.saturating_sub(1) })
+                }
+                (Observation::HigherThanPrevious, Some(prev)) => {
+                    Some(PriceBand { min: prev.min.saturating_add(1), max: u32::MAX })
+                }
+                (_, None) => None,
+            };
+            bands.push(band);
+        }
+        return bands;
+    }
+
+    /// The exact price, if known.
+    pub fn price(&self) -> Option<u32> {
+        match self {
+            Observation::Exact(p) => Some(*p),
+            _ => None,
+        }
+    }
+
+    /// Whether anything at all is known about the price.
+    pub fn is_known(&self) -> bool {
+        *self != Observation::Unknown
+    }
+}
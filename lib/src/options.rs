@@ -0,0 +1,53 @@
+use crate::error::CalcError;
+use crate::pattern::{Pattern, PatternDistribution};
+use crate::transition::TransitionMatrix;
+
+/// Settings which change how the calculator interprets the prices.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Options {
+    /// The probability that any given price was entered wrongly, e.g. a typo
+    /// or a misremembered price. If this is zero (the default), every price is
+    /// trusted completely, so a single wrong price can rule out a pattern.
+    ///
+    /// Most wrong prices are taken to be a slip of a single key: one too high
+    /// or low, one digit wrong, or two neighbouring digits swapped. The rest
+    /// could be any price up to 9999, as could a wrong range or comparison. A
+    /// wrong price only counts towards how likely each pattern is; it doesn't
+    /// narrow down what the real price was.
+    pub error_rate: f64,
+    /// The chance of each pattern given last week's pattern.
+    pub transitions: TransitionMatrix,
+    /// Whether this is the first week of buying turnips on the island, which
+    /// always follows the Small Spike pattern. There is no last week to give.
+    pub first_week: bool,
+    /// Whether to reproduce the game's 32-bit floating point arithmetic exactly,
+    /// rather than treating the hidden rates as exact numbers. The two only
+    /// differ for prices right at the edge of what a pattern allows: both accept
+    /// every price the game can produce, but the default occasionally accepts
+    /// one there that it can't.
+    pub float32: bool,
+}
+
+impl Options {
+    /// Check that the options make sense.
+    pub(crate) fn validate(&self) -> Result<(), CalcError> {
+        if !(0.0..1.0).contains(&self.error_rate) {
+            return Err(CalcError::InvalidOptions("The error rate must be at least 0 and less than 1."));
+        }
+        self.transitions.validate()?;
+        return Ok(());
+    }
+
+    /// Get the chance of each pattern this week, given last week's.
+    pub(crate) fn priors(&self, last_week: &PatternDistribution) -> Result<PatternDistribution, CalcError> {
+        if self.first_week {
+            if last_week.known() > 0.0 {
+                return Err(CalcError::InvalidOptions("There is no last week in the first week on an island."));
+            }
+            return Ok(Some(Pattern::SmallSpike).into());
+        }
+        return Ok(self.transitions.next_week(last_week));
+    }
+}
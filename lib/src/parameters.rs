@@ -0,0 +1,227 @@
+use crate::calculator::Calculator;
+use crate::error::CalcError;
+use crate::node::{smooth, Node, Smoothed, Step};
+use crate::observation::Observation;
+use crate::options::Options;
+use crate::pattern::{Pattern, PatternDistribution};
+use crate::traverse;
+
+/// What the prices reveal about the hidden values the game chose for a pattern.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HiddenParameters {
+    /// The pattern this describes.
+    pub pattern: Pattern,
+    /// The probability of this pattern.
+    pub probability: f64,
+    /// Each phase of the pattern, in order, given this pattern.
+    pub phases: Vec<PhaseParameters>,
+    /// The factor of the base price at the peak of the spike, given this
+    /// pattern, for the spike patterns.
+    pub peak_rate: Option<RateDistribution>,
+}
+
+/// What the prices reveal about a single phase of a pattern.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PhaseParameters {
+    /// The name of the phase.
+    pub phase: String,
+    /// Each possible length of the phase and its probability, in ascending
+    /// order of length. Phases that can be skipped may have length 0.
+    pub lengths: Vec<(i32, f64)>,
+    /// For decreasing phases, the factor of the base price the phase started
+    /// at, given that the phase happened.
+    pub initial_rate: Option<RateDistribution>,
+}
+
+/// The distribution of a hidden factor of the base price, made up of the ranges
+/// it could be in, each with its probability and the mean factor within it.
+/// The ranges can overlap, since different ways of getting to the same prices
+/// can allow different factors.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RateDistribution {
+    /// Each range, in ascending order of its minimum.
+    pub ranges: Vec<RateRange>,
+}
+
+/// A range of factors of the base price that a hidden factor could be in.
+/// Factors within the range aren't necessarily equally likely: the factor of a
+/// decreasing phase is likelier towards the middle of its range.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RateRange {
+    /// The lowest factor in the range.
+    pub min: f64,
+    /// The factor just above the highest in the range.
+    pub max: f64,
+    /// The probability that the factor is in this range.
+    pub probability: f64,
+    /// The mean factor, given that it is in this range.
+    pub mean: f64,
+}
+
+impl RateDistribution {
+    /// Build the distribution from ranges whose probabilities may not add up to 1.
+    fn new(ranges: Vec<RateRange>) -> Self {
+        let total: f64 = ranges.iter().map(|range| range.probability).sum();
+        let mut merged: Vec<RateRange> = Vec::new();
+        for range in ranges {
+            // Many paths share the same range, so only list it once.
+            let probability = range.probability / total;
+            match merged.iter_mut().find(|r| r.min == range.min && r.max == range.max) {
+                Some(existing) if existing.probability + probability > 0.0 => {
+                    existing.mean = (existing.mean * existing.probability + range.mean * probability)
+                        / (existing.probability + probability);
+                    existing.probability += probability;
+                }
+                Some(_) => {}
+                None => merged.push(RateRange { probability, ..range }),
+            }
+        }
+        merged.sort_by(|a, b| a.min.total_cmp(&b.min).then(a.max.total_cmp(&b.max)));
+        return RateDistribution { ranges: merged };
+    }
+
+    /// The lowest factor that is still possible.
+    pub fn min(&self) -> f64 {
+        self.ranges.iter().map(|range| range.min).fold(f64::INFINITY, f64::min)
+    }
+
+    /// The factor just above the highest that is still possible.
+    pub fn max(&self) -> f64 {
+        self.ranges.iter().map(|range| range.max).fold(f64::NEG_INFINITY, f64::max)
+    }
+
+    /// The expected (mean) factor.
+    pub fn expected(&self) -> f64 {
+        self.ranges.iter()
+            .map(|range| range.mean * range.probability)
+            .sum()
+    }
+}
+
+/// Calculate what the prices reveal about the hidden values the game chose,
+/// for each pattern that is still possible: the length of each phase, the
+/// factor each decreasing phase started at, and the factor at the peak of the
+/// spike. This is most useful once most of the week is known, to check that the
+/// prices are consistent with the patterns.
+pub fn hidden_parameters(last_week: impl Into<PatternDistribution>, base_price: Option<u32>,
+                         prices: Vec<Observation>, options: &Options) -> Result<Vec<HiddenParameters>, CalcError> {
+    return traverse(last_week, base_price, prices, options, false)?.hidden_parameters();
+}
+
+impl Calculator {
+    /// Calculate what the prices reveal about the hidden values the game chose.
+    pub fn hidden_parameters(&self) -> Result<Vec<HiddenParameters>, CalcError> {
+        let nodes = self.complete()?;
+        let total: f64 = nodes.iter().map(|n| n.value().1).sum();
+
+        let mut results = Vec::new();
+        for pattern in Pattern::ALL {
+            let nodes: Vec<Node> = nodes.iter().filter(|n| n.value().0 == pattern).cloned().collect();
+            let probability: f64 = nodes.iter().map(|n| n.value().1).sum();
+            if probability == 0.0 {
+                continue;
+            }
+
+            // Merged nodes always went through the same phases, so any one of
+            // their paths gives the lengths.
+            let paths: Vec<(Vec<&Step>, f64)> = nodes.iter().map(|n| (n.path(), n.value().1)).collect();
+            // The rates come from every way of getting to the nodes, given every price.
+            let steps = smooth(&nodes);
+
+            // Phases are matched up by name, since some can be skipped.
+            let mut names: Vec<String> = Vec::new();
+            let path_phases: Vec<Vec<Phase>> = paths.iter().map(|(path, _)| phases(path)).collect();
+            for phases in &path_phases {
+                let mut position = 0;
+                for phase in phases {
+                    match names.iter().position(|name| *name == phase.name) {
+                        Some(existing) => position = existing + 1,
+                        None => {
+                            names.insert(position, phase.name.clone());
+                            position += 1;
+                        }
+                    }
+                }
+            }
+
+            let phases = names.into_iter().map(|name| {
+                let mut lengths: Vec<(i32, f64)> = Vec::new();
+                for ((_, prob), phases) in paths.iter().zip(&path_phases) {
+                    let prob = prob / probability;
+                    let phase = phases.iter().find(|phase| phase.name == name);
+                    let length = phase.map_or(0, |phase| phase.steps.len() as i32);
+                    match lengths.iter_mut().find(|(l, _)| *l == length) {
+                        Some((_, existing)) => *existing += prob,
+                        None => lengths.push((length, prob)),
+                    }
+                }
+                lengths.sort_by_key(|(length, _)| *length);
+
+                // A decreasing phase started at the rate of its first half-day.
+                let rates: Vec<RateRange> = steps.iter()
+                    .filter(|s| s.step.name == name && s.step.position == 1 && s.step.decrement.is_some())
+                    .map(rate_range)
+                    .collect();
+
+                PhaseParameters {
+                    phase: name,
+                    lengths,
+                    initial_rate: if rates.is_empty() { None } else { Some(RateDistribution::new(rates)) },
+                }
+            }).collect();
+
+            let peak_offset = match pattern {
+                Pattern::SmallSpike => Some(3),
+                Pattern::LargeSpike => Some(2),
+                _ => None,
+            };
+            let peak_rate = peak_offset.map(|offset| {
+                // The spike always follows the initial decreasing phase, and
+                // each of its half-days is a phase of its own.
+                RateDistribution::new(steps.iter()
+                    .filter(|s| s.step.phase == 1 + offset)
+                    .map(rate_range)
+                    .collect())
+            });
+
+            results.push(HiddenParameters {
+                pattern,
+                probability: probability / total,
+                phases,
+                peak_rate,
+            });
+        }
+
+        return Ok(results);
+    }
+}
+
+/// The half-days of a path that belong to one phase.
+struct Phase<'a> {
+    name: String,
+    steps: Vec<&'a Step>,
+}
+
+/// Split a path into its phases. A spike is made of several phases with the
+/// same name, which are counted as one.
+fn phases<'a>(history: &[&'a Step]) -> Vec<Phase<'a>> {
+    let mut phases: Vec<Phase> = Vec::new();
+    for &step in history {
+        match phases.last_mut() {
+            Some(phase) if phase.name == step.name => phase.steps.push(step),
+            _ => phases.push(Phase { name: step.name.clone(), steps: vec![step] }),
+        }
+    }
+    return phases;
+}
+
+/// Get the range of factors a half-day could have had, given every price, with
+/// the probability of the way it was reached.
+fn rate_range(smoothed: &Smoothed) -> RateRange {
+    let (min, max) = smoothed.smoothed.rates();
+    return RateRange { min, max, probability: smoothed.prob, mean: smoothed.smoothed.mean_rate() };
+}
@@ -1,5 +1,9 @@
+use crate::analysis::Analysis;
+use crate::error::CalcError;
+
 /// Possible patterns for the week.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Pattern {
     Decreasing,
     Random,
@@ -8,7 +12,26 @@ pub enum Pattern {
 }
 
 impl Pattern {
-    /// Prior probability of this pattern occurring, given last week's pattern.
+    /// All the patterns, in a stable order.
+    pub const ALL: [Pattern; 4] = [
+        Pattern::Decreasing,
+        Pattern::Random,
+        Pattern::SmallSpike,
+        Pattern::LargeSpike,
+    ];
+
+    /// The position of this pattern within `Pattern::ALL`.
+    pub(crate) fn index(&self) -> usize {
+        match self {
+            Pattern::Decreasing => 0,
+            Pattern::Random => 1,
+            Pattern::SmallSpike => 2,
+            Pattern::LargeSpike => 3,
+        }
+    }
+
+    /// Prior probability of this pattern occurring, given last week's pattern,
+    /// according to the New Horizons table. See `TransitionMatrix` for others.
     pub fn prior(&self, prev: Option<Pattern>) -> f64 {
         match prev {
             None => {
@@ -55,3 +78,94 @@ impl Pattern {
         }
     }
 }
+
+/// How likely each pattern is to have happened in a week that wasn't fully
+/// observed, e.g. last week.
+/// Any probability not assigned to a pattern means we don't know, so it
+/// follows the average chances used when last week's pattern is unknown.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RawDistribution"))]
+pub struct PatternDistribution {
+    /// The probability of each pattern, in the order of `Pattern::ALL`.
+    probabilities: [f64; 4],
+}
+
+impl PatternDistribution {
+    /// Create a distribution from the probability of some patterns.
+    /// The probabilities must not be negative or add up to more than 1; anything
+    /// left over is unknown.
+    pub fn new(probabilities: &[(Pattern, f64)]) -> Result<Self, CalcError> {
+        let mut distribution = PatternDistribution::default();
+        for (pattern, prob) in probabilities {
+            if prob.is_nan() || *prob < 0.0 {
+                return Err(CalcError::InvalidDistribution("Probabilities must not be negative."));
+            }
+            distribution.probabilities[pattern.index()] += prob;
+        }
+        // Allow a little leeway for rounding, e.g. three patterns at 33.3%.
+        if distribution.probabilities.iter().sum::<f64>() > 1.0 + 1e-6 {
+            return Err(CalcError::InvalidDistribution("Probabilities must not add up to more than 1."));
+        }
+        return Ok(distribution);
+    }
+
+    /// The probability of the given pattern.
+    pub fn probability(&self, pattern: Pattern) -> f64 {
+        self.probabilities[pattern.index()]
+    }
+
+    /// The probability of each pattern, in the order of `Pattern::ALL`.
+    pub fn probabilities(&self) -> [(Pattern, f64); 4] {
+        return Pattern::ALL.map(|pattern| (pattern, self.probability(pattern)));
+    }
+
+    /// The probability of each pattern, in descending order of likelihood.
+    pub fn ranked(&self) -> [(Pattern, f64); 4] {
+        let mut results = self.probabilities();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1));
+        return results;
+    }
+
+    /// The total probability assigned to patterns, rather than being unknown.
+    pub fn known(&self) -> f64 {
+        self.probabilities.iter().sum::<f64>().min(1.0)
+    }
+}
+
+/// A `PatternDistribution` as saved, which is checked by `PatternDistribution::new`
+/// before it can be used.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawDistribution {
+    probabilities: [f64; 4],
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawDistribution> for PatternDistribution {
+    type Error = CalcError;
+
+    fn try_from(raw: RawDistribution) -> Result<Self, Self::Error> {
+        return PatternDistribution::new(&Pattern::ALL.map(|pattern| (pattern, raw.probabilities[pattern.index()])));
+    }
+}
+
+impl From<Option<Pattern>> for PatternDistribution {
+    fn from(pattern: Option<Pattern>) -> Self {
+        let mut distribution = PatternDistribution::default();
+        if let Some(p) = pattern {
+            distribution.probabilities[p.index()] = 1.0;
+        }
+        return distribution;
+    }
+}
+
+impl From<&Analysis> for PatternDistribution {
+    fn from(analysis: &Analysis) -> Self {
+        let mut distribution = PatternDistribution::default();
+        for (pattern, prob) in analysis.probabilities() {
+            distribution.probabilities[pattern.index()] = prob;
+        }
+        return distribution;
+    }
+}
@@ -0,0 +1,64 @@
+use crate::calculator::Calculator;
+use crate::error::CalcError;
+use crate::observation::Observation;
+use crate::options::Options;
+use crate::pattern::{Pattern, PatternDistribution};
+use crate::traverse;
+
+/// A possible position within a pattern.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PhaseState {
+    /// The pattern.
+    pub pattern: Pattern,
+    /// The name of the phase within the pattern.
+    pub phase: String,
+    /// How far into the phase we are, starting from 1.
+    pub position: i32,
+    /// The probability of being at this position.
+    pub probability: f64,
+}
+
+/// Calculate where in the week's pattern the most recent half-day was, as a
+/// list of possible positions sorted in descending order of likelihood.
+/// If no half-days have passed, the result is empty.
+pub fn current_phase(last_week: impl Into<PatternDistribution>, base_price: Option<u32>,
+                     prices: Vec<Observation>, options: &Options) -> Result<Vec<PhaseState>, CalcError> {
+    return traverse(last_week, base_price, prices, options, false)?.current_phase();
+}
+
+impl Calculator {
+    /// Calculate where in the week's pattern the most recent half-day was.
+    pub fn current_phase(&self) -> Result<Vec<PhaseState>, CalcError> {
+        let nodes = self.nodes()?;
+        let total: f64 = nodes.iter().map(|n| n.value().1).sum();
+
+        let mut results: Vec<PhaseState> = Vec::new();
+        for node in nodes {
+            let (pattern, prob) = node.value();
+            let last = match node.path().last() {
+                Some(&last) => last,
+                None => continue,
+            };
+
+            let existing = results.iter_mut().find(|state| {
+                state.pattern == pattern && state.phase == last.name
+                    && state.position == last.position
+            });
+            match existing {
+                Some(state) => state.probability += prob / total,
+                None => results.push(PhaseState {
+                    pattern,
+                    phase: last.name.clone(),
+                    position: last.position,
+                    probability: prob / total,
+                }),
+            }
+        }
+
+        // Sort descending.
+        results.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+
+        return Ok(results);
+    }
+}
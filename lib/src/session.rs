@@ -0,0 +1,97 @@
+use crate::calculator::Calculator;
+use crate::error::CalcError;
+use crate::node::MAX_HALF_DAYS;
+use crate::observation::Observation;
+use crate::options::Options;
+use crate::pattern::PatternDistribution;
+#[cfg(feature = "serde")]
+use crate::pattern::Pattern;
+
+/// The version of `Session` written by this version of the library.
+/// This must be increased whenever the meaning of a saved session changes.
+/// Version 1 only had last week's pattern, as `prev_pattern`, rather than a distribution.
+pub const SESSION_VERSION: u32 = 2;
+
+/// Everything that was seen during a single week.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Week {
+    /// The base price, if known.
+    pub base_price: Option<u32>,
+    /// The prices observed, in order.
+    pub observations: Vec<Observation>,
+}
+
+/// A saved calculator, which can be stored and later restored.
+/// Only the inputs are kept; the pattern trees are rebuilt on restoring.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "RawSession"))]
+pub struct Session {
+    /// The version of the library's format that wrote this session.
+    pub version: u32,
+    /// How likely each pattern was last week.
+    pub last_week: PatternDistribution,
+    /// This week's prices.
+    pub week: Week,
+    /// Settings for interpreting the prices.
+    pub options: Options,
+}
+
+/// A `Session` as saved by any version, which is migrated to the current one.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawSession {
+    version: u32,
+    #[serde(default)]
+    last_week: Option<PatternDistribution>,
+    #[serde(default)]
+    prev_pattern: Option<Pattern>,
+    week: Week,
+    options: Options,
+}
+
+#[cfg(feature = "serde")]
+impl From<RawSession> for Session {
+    fn from(raw: RawSession) -> Self {
+        return Session {
+            // Older sessions are now in the current format, but sessions from
+            // the future are left alone so that restoring them still fails.
+            version: raw.version.max(SESSION_VERSION),
+            last_week: raw.last_week.unwrap_or_else(|| raw.prev_pattern.into()),
+            week: raw.week,
+            options: raw.options,
+        };
+    }
+}
+
+impl Calculator {
+    /// Save the inputs of this calculator.
+    pub fn session(&self) -> Session {
+        Session {
+            version: SESSION_VERSION,
+            last_week: *self.last_week(),
+            week: Week {
+                base_price: self.base_price(),
+                observations: self.observations().to_vec(),
+            },
+            options: self.options().clone(),
+        }
+    }
+
+    /// Restore a calculator from a saved session.
+    /// The session is checked like any other input, so a malformed one gives an
+    /// error rather than a made-up analysis.
+    pub fn restore(session: Session) -> Result<Self, CalcError> {
+        if session.version > SESSION_VERSION {
+            return Err(CalcError::UnsupportedVersion(session.version));
+        }
+        session.options.validate()?;
+        if session.week.observations.len() > MAX_HALF_DAYS as usize {
+            return Err(CalcError::TooManyPrices(session.week.observations.len()));
+        }
+        let mut calculator = Calculator::new(session.last_week, session.week.base_price, session.options)?;
+        calculator.extend(session.week.observations)?;
+        return Ok(calculator);
+    }
+}
@@ -1,32 +1,42 @@
+use std::collections::BTreeMap;
+
 use insta::assert_debug_snapshot;
+#[cfg(feature = "serde")]
+use insta::assert_snapshot;
 
-use super::{Pattern, run};
+use super::{
+    current_phase, diagnose, distribution, explain, fit_transitions, forecast, hidden_parameters, history,
+    max_price, next_week, run, spike_timing, CalcError, Calculator, Observation, Options, Pattern,
+    PatternDistribution, Session, TransitionMatrix, Week, SESSION_VERSION,
+};
+#[cfg(feature = "serde")]
+use super::Analysis;
 
-// Map a Vec<T> into a Vec<Option<T>> by wrapping in Some.
-macro_rules! map_some {
+// Map a Vec<u32> into a Vec<Observation> of exact prices.
+macro_rules! map_exact {
     ($vec:expr) => {{
-        $vec.into_iter().map(Some).collect()
+        $vec.into_iter().map(Observation::Exact).collect()
     }}
 }
 
 // Assert that the results contain only the given pattern with 100% chance.
 macro_rules! assert_only {
     ($results:expr, $pattern:ident) => {{
-        assert_eq!($results.len(), 1);
-        assert_eq!($results[0].0, Pattern::$pattern);
-        assert_eq!($results[0].1, 1.0)
+        for (pattern, prob) in $results.probabilities() {
+            if pattern == Pattern::$pattern {
+                assert_eq!(prob, 1.0);
+            } else {
+                assert_eq!(prob, 0.0);
+            }
+        }
     }}
 }
 
 // Assert that the probability of a pattern is higher in one result than another.
 macro_rules! assert_gt {
     ($results1:expr, $results2:expr, $pattern:ident) => {{
-        let prob1 = $results1.iter()
-            .find(|(p, _)| *p == Pattern::$pattern)
-            .unwrap().1;
-        let prob2 = $results2.iter()
-            .find(|(p, _)| *p == Pattern::$pattern)
-            .unwrap().1;
+        let prob1 = $results1.probability(Pattern::$pattern);
+        let prob2 = $results2.probability(Pattern::$pattern);
         assert!(prob1 > prob2);
     }}
 }
@@ -38,7 +48,7 @@ fn test_decreasing_full() {
         90, 87, 82, 78,
         74, 69, 66, 61,
         58, 54, 50, 47];
-    let results = run(None, base_price, map_some!(prices), true);
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
     assert_only!(results, Decreasing);
 }
 
@@ -48,7 +58,7 @@ fn test_decreasing_minimal() {
     let prices = vec![
         90, 87, 82, 78,
         74, 69, 66, 61];
-    let results = run(None, base_price, map_some!(prices), true);
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
     assert_only!(results, Decreasing);
 }
 
@@ -58,10 +68,170 @@ fn test_decreasing_partial() {
     let prices = vec![
         90, 87, 82, 78,
         74, 69, 66];
-    let results = run(None, base_price, map_some!(prices), true);
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
     assert_debug_snapshot!(results);
 }
 
+#[test]
+fn test_decreasing_rate() {
+    // A price of 85 means the rate was exactly its minimum of 0.85, so the next
+    // price can only drop to 80, which rules out Decreasing and Large Spike.
+    let base_price = 100;
+    let results = run(None, Some(base_price), map_exact!(vec![85, 79]), &Options::default(), false).unwrap();
+    assert_only!(results, SmallSpike);
+
+    // Over a long run of falling prices, the rate doesn't lose precision.
+    let prices = vec![85, 80, 75, 70, 65, 60, 55, 50];
+    let calculator = Calculator::with_prices(None, Some(base_price), map_exact!(prices),
+                                             Options::default()).unwrap();
+    assert_only!(calculator.analysis().unwrap(), Decreasing);
+    let forecast = calculator.forecast().unwrap();
+    assert_eq!(forecast[0].overall.min, 45);
+    assert_eq!(forecast[3].overall.min, 30);
+}
+
+// Work out the distribution of the price on each half-day of a Decreasing week,
+// given some of its prices, by following the game's random draws on a fine
+// grid of rates, kept separately for each history of prices.
+fn decreasing_by_simulation(base_price: u32, prices: &[Option<u32>], points: usize) -> Vec<BTreeMap<u32, f64>> {
+    let step = 1.0 / points as f64;
+    let rate = |i: usize| (i as f64 + 0.5) * step;
+    let price = |i: usize| (rate(i) * base_price as f64 + 0.99999) as u32;
+    // The game takes off 0.03 and then up to another 0.02.
+    let (near, far) = ((0.03 * points as f64).round() as usize, (0.05 * points as f64).round() as usize);
+
+    let mut start = vec![0.0; points];
+    for (i, weight) in start.iter_mut().enumerate() {
+        if (0.85..0.90).contains(&rate(i)) {
+            *weight = 1.0;
+        }
+    }
+    let mut histories: Vec<(Vec<u32>, Vec<f64>)> = vec![(Vec::new(), start)];
+    for (half_day, known) in prices.iter().enumerate() {
+        if half_day > 0 {
+            for (_, weights) in histories.iter_mut() {
+                let mut sums = vec![0.0; points + 1];
+                for i in 0..points {
+                    sums[i + 1] = sums[i] + weights[i];
+                }
+                *weights = (0..points).map(|i| {
+                    let (from, to) = ((i + near).min(points), (i + far).min(points));
+                    (sums[to] - sums[from]) / (far - near) as f64
+                }).collect();
+            }
+        }
+
+        // Split each history by the price each rate gives, keeping only the known price.
+        let mut split: BTreeMap<Vec<u32>, Vec<f64>> = BTreeMap::new();
+        for (history, weights) in histories {
+            for (i, weight) in weights.iter().enumerate().filter(|(_, weight)| **weight > 0.0) {
+                if known.is_none_or(|known| known == price(i)) {
+                    let mut history = history.clone();
+                    history.push(price(i));
+                    split.entry(history).or_insert_with(|| vec![0.0; points])[i] = *weight;
+                }
+            }
+        }
+        histories = split.into_iter().collect();
+    }
+
+    let total: f64 = histories.iter().flat_map(|(_, weights)| weights).sum();
+    return (0..prices.len()).map(|half_day| {
+        let mut probabilities = BTreeMap::new();
+        for (history, weights) in histories.iter() {
+            *probabilities.entry(history[half_day]).or_insert(0.0) += weights.iter().sum::<f64>() / total;
+        }
+        probabilities
+    }).collect();
+}
+
+#[test]
+fn test_decreasing_density() {
+    let base_price = 100;
+    let options = Options { transitions: TransitionMatrix::default().with_unknown([1.0, 0.0, 0.0, 0.0]).unwrap(),
+                            ..Options::default() };
+
+    // A price of 90 leaves the rate anywhere from 0.89 to 0.90, and taking off
+    // 0.03 to 0.05 makes the middle of the next range twice as likely as either end.
+    let results = distribution(None, Some(base_price), map_exact!(vec![90]), &options).unwrap();
+    assert!((results[1].probability(85) - 0.25).abs() < 1e-5);
+    assert!((results[1].probability(86) - 0.5).abs() < 1e-5);
+    assert!((results[1].probability(87) - 0.25).abs() < 1e-5);
+
+    // Further on, the distribution matches following the game's draws, both
+    // for future prices and for missed prices with known prices after them.
+    let weeks = [
+        vec![Some(90), Some(85), None, None],
+        vec![Some(90), None, Some(84)],
+        vec![None, Some(86), None, Some(79), None],
+    ];
+    for prices in weeks {
+        let expected = decreasing_by_simulation(base_price, &prices, 100_000);
+        let observations = prices.iter().copied().map(Observation::from).collect();
+        let results = distribution(None, Some(base_price), observations, &options).unwrap();
+        for (expected, result) in expected.iter().zip(&results) {
+            let prices: Vec<u32> = result.probabilities.iter().map(|(price, _)| *price).collect();
+            for price in prices.iter().chain(expected.keys()) {
+                let expected = expected.get(price).copied().unwrap_or(0.0);
+                assert!((result.probability(*price) - expected).abs() < 1e-3,
+                        "{:?}: {} has {} not {}", prices, price, result.probability(*price), expected);
+            }
+        }
+    }
+
+    // So the chance of each pattern uses it too: after 90, a price of 86 is
+    // twice as likely in Decreasing as 85.
+    let middle = run(None, Some(base_price), map_exact!(vec![90, 86]), &options, false).unwrap();
+    let end = run(None, Some(base_price), map_exact!(vec![90, 85]), &options, false).unwrap();
+    assert!((middle.evidence() / end.evidence() - 2.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_float32() {
+    let float32 = Options { float32: true, ..Options::default() };
+
+    // Weeks produced by the game's code with rates at the very ends of their
+    // ranges, which only the game's rounding makes possible. Both modes must
+    // accept every price the game can produce.
+    let weeks = [
+        (Pattern::Decreasing, 100, vec![85, 80, 78, 73, 68, 63, 60, 55, 51, 46, 43, 38]),
+        (Pattern::SmallSpike, 90, vec![55, 52, 48, 43, 38, 90, 89, 135, 170, 165, 81, 77]),
+        (Pattern::SmallSpike, 100, vec![55, 122, 140, 139, 140, 139, 90, 85, 83, 78, 73, 70]),
+        (Pattern::LargeSpike, 100, vec![85, 83, 80, 90, 140, 600, 140, 119, 40, 40, 40, 54]),
+    ];
+    for (pattern, base_price, prices) in weeks {
+        let results = run(None, Some(base_price), map_exact!(prices.clone()), &Options::default(), false).unwrap();
+        assert!(results.probability(pattern) > 0.0);
+        let results = run(None, Some(base_price), map_exact!(prices.clone()), &float32, false).unwrap();
+        assert!(results.probability(pattern) > 0.0);
+
+        // Partway through, the float32 forecast never allows a price that the
+        // default doesn't.
+        let wide = forecast(None, Some(base_price), map_exact!(prices[..6].iter().copied()), &Options::default()).unwrap();
+        let narrow = forecast(None, Some(base_price), map_exact!(prices[..6].iter().copied()), &float32).unwrap();
+        assert_eq!(wide.len(), narrow.len());
+        for (wide, narrow) in wide.iter().zip(&narrow) {
+            assert!(wide.overall.min <= narrow.overall.min && narrow.overall.max <= wide.overall.max);
+        }
+    }
+
+    // Decreasing takes up to 0.05 off 0.9, while Large Spike picks a rate
+    // between 0.9 and 0.85, which rounds differently. Only Decreasing can
+    // start just below 0.85, which only float32 can tell.
+    let results = run(None, Some(100), map_exact!(vec![85, 80]), &float32, false).unwrap();
+    assert!(results.probability(Pattern::Decreasing) > 0.0);
+    assert_eq!(results.probability(Pattern::LargeSpike), 0.0);
+    let results = run(None, Some(100), map_exact!(vec![85, 80]), &Options::default(), false).unwrap();
+    assert!(results.probability(Pattern::LargeSpike) > 0.0);
+
+    // Prices the game can't produce are ruled out, even where the default's
+    // tolerance lets them through.
+    let results = run(None, Some(100), map_exact!(vec![81, 75]), &float32, false);
+    assert_eq!(results, Err(CalcError::NoMatchingPattern));
+    let results = run(None, Some(100), map_exact!(vec![81, 75]), &Options::default(), false);
+    assert!(results.is_ok());
+}
+
 #[test]
 fn test_random_full() {
     let base_price = 95;
@@ -70,7 +240,7 @@ fn test_random_full() {
         65, 59,
         96, 121,
         57, 53, 43];
-    let results = run(None, base_price, map_some!(prices), true);
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
     assert_only!(results, Random);
 }
 
@@ -78,7 +248,7 @@ fn test_random_full() {
 fn test_random_minimal() {
     let base_price = 95;
     let prices = vec![102, 127, 112];
-    let results = run(None, base_price, map_some!(prices), true);
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
     assert_only!(results, Random);
 }
 
@@ -86,7 +256,7 @@ fn test_random_minimal() {
 fn test_random_partial() {
     let base_price = 95;
     let prices = vec![102, 127];
-    let results = run(None, base_price, map_some!(prices), true);
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
     assert_debug_snapshot!(results);
 }
 
@@ -97,7 +267,7 @@ fn test_small_spike_full() {
         55, 52, 48, 43, 38,
         90, 89, 135, 170, 165,
         81, 77];
-    let results = run(None, base_price, map_some!(prices), true);
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
     assert_only!(results, SmallSpike);
 }
 
@@ -105,15 +275,38 @@ fn test_small_spike_full() {
 fn test_small_spike_minimal() {
     let base_price = 90;
     let prices = vec![55, 52, 48, 43];
-    let results = run(None, base_price, map_some!(prices), true);
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
     assert_only!(results, SmallSpike);
 }
 
+#[test]
+fn test_small_spike_peak() {
+    // The prices either side of the peak are always lower than it.
+    let base_price = 90;
+    let prices = vec![
+        55, 52, 48, 43, 38,
+        90, 89, 135, 170, 173,
+        81, 77];
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), false);
+    assert_eq!(results, Err(CalcError::NoMatchingPattern));
+    let prices = vec![55, 52, 48, 43, 38, 90, 89, 170, 167];
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), false);
+    assert_eq!(results, Err(CalcError::NoMatchingPattern));
+
+    // So once the peak is seen, the next price can't be much higher than it,
+    // rather than anything up to twice the base price.
+    let prices = vec![55, 52, 48, 43, 38, 90, 89, 135, 150];
+    let results = forecast(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+    assert_eq!(results[0].half_day, 9);
+    assert_eq!(results[0].overall.min, 125);
+    assert_eq!(results[0].overall.max, 149);
+}
+
 #[test]
 fn test_small_spike_partial() {
     let base_price = 90;
     let prices = vec![55, 52, 48];
-    let results = run(None, base_price, map_some!(prices), true);
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
     assert_debug_snapshot!(results);
 }
 
@@ -125,7 +318,7 @@ fn test_large_spike_full() {
         128, 165, 455,
         147, 143,
         57, 53, 43, 94, 42];
-    let results = run(None, base_price, map_some!(prices), true);
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
     assert_only!(results, LargeSpike);
 }
 
@@ -135,7 +328,7 @@ fn test_large_spike_minimal() {
     let prices = vec![
         90, 86,
         128, 165];
-    let results = run(None, base_price, map_some!(prices), true);
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
     assert_only!(results, LargeSpike);
 }
 
@@ -143,46 +336,963 @@ fn test_large_spike_minimal() {
 fn test_large_spike_partial() {
     let base_price = 104;
     let prices = vec![90, 86];
-    let results = run(None, base_price, map_some!(prices), true);
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
     assert_debug_snapshot!(results);
 }
 
 #[test]
 fn test_prev_patterns() {
     let base_price = 104;
-    let prices: Vec<Option<u32>> = map_some!(vec![90, 86]);
-    let results_plain = run(None, base_price, prices.clone(), true);
-    let results_ls = run(Some(Pattern::LargeSpike), base_price, prices.clone(), true);
-    let results_d = run(Some(Pattern::Decreasing), base_price, prices, true);
+    let prices: Vec<Observation> = map_exact!(vec![90, 86]);
+    let results_plain = run(None, Some(base_price), prices.clone(), &Options::default(), true).unwrap();
+    let results_ls = run(Some(Pattern::LargeSpike), Some(base_price), prices.clone(), &Options::default(), true).unwrap();
+    let results_d = run(Some(Pattern::Decreasing), Some(base_price), prices, &Options::default(), true).unwrap();
     assert_gt!(results_d, results_plain, LargeSpike);
     assert_gt!(results_plain, results_ls, LargeSpike);
 }
 
+#[test]
+fn test_last_week() {
+    let base_price = 104;
+    let prices: Vec<Observation> = map_exact!(vec![90, 86]);
+    let analyse = |last_week: PatternDistribution| {
+        let mut calculator = Calculator::new(last_week, Some(base_price), Options::default()).unwrap();
+        calculator.extend(prices.clone()).unwrap();
+        calculator.analysis().unwrap()
+    };
+
+    // A certain pattern is the same as knowing it, and no pattern is the same as not knowing.
+    for pattern in Pattern::ALL {
+        assert_eq!(analyse(Some(pattern).into()),
+                   run(Some(pattern), Some(base_price), prices.clone(), &Options::default(), false).unwrap());
+    }
+    assert_eq!(analyse(PatternDistribution::default()),
+               run(None, Some(base_price), prices.clone(), &Options::default(), false).unwrap());
+
+    // An uncertain pattern mixes the chances for each pattern it could have been.
+    let transitions = TransitionMatrix::default();
+    let mixed = PatternDistribution::new(&[(Pattern::LargeSpike, 0.7), (Pattern::SmallSpike, 0.3)]).unwrap();
+    for pattern in Pattern::ALL {
+        let expected = 0.7 * pattern.prior(Some(Pattern::LargeSpike))
+            + 0.3 * pattern.prior(Some(Pattern::SmallSpike));
+        assert!((transitions.next_week(&mixed).probability(pattern) - expected).abs() < 1e-9);
+    }
+    let results = analyse(mixed);
+    assert_gt!(results, analyse(Some(Pattern::LargeSpike).into()), LargeSpike);
+    assert_gt!(analyse(Some(Pattern::SmallSpike).into()), results, LargeSpike);
+    assert_eq!(run(mixed, Some(base_price), prices.clone(), &Options::default(), false).unwrap(), results);
+
+    // Anything left over is unknown.
+    let partial = PatternDistribution::new(&[(Pattern::Decreasing, 0.5)]).unwrap();
+    for pattern in Pattern::ALL {
+        let expected = 0.5 * pattern.prior(Some(Pattern::Decreasing)) + 0.5 * pattern.prior(None);
+        assert!((transitions.next_week(&partial).probability(pattern) - expected).abs() < 1e-9);
+    }
+
+    // Last week's analysis can be used directly.
+    let last_week = run(None, Some(100), map_exact!(vec![90, 85, 80, 140]), &Options::default(), false).unwrap();
+    let distribution = PatternDistribution::from(&last_week);
+    for (pattern, prob) in last_week.probabilities() {
+        assert_eq!(distribution.probability(pattern), prob);
+    }
+    assert_eq!(run(&last_week, Some(base_price), prices.clone(), &Options::default(), false).unwrap(),
+               analyse(distribution));
+
+    // Impossible distributions are rejected.
+    assert!(matches!(PatternDistribution::new(&[(Pattern::Random, -0.1)]),
+                     Err(CalcError::InvalidDistribution(_))));
+    assert!(matches!(PatternDistribution::new(&[(Pattern::Random, f64::NAN)]),
+                     Err(CalcError::InvalidDistribution(_))));
+    assert!(matches!(PatternDistribution::new(&[(Pattern::Random, 0.6), (Pattern::Decreasing, 0.6)]),
+                     Err(CalcError::InvalidDistribution(_))));
+}
+
+#[test]
+fn test_transitions() {
+    // The default is the table from the game, whose unknown row approximates
+    // the stationary distribution.
+    let transitions = TransitionMatrix::default();
+    for prev in [None, Some(Pattern::Decreasing), Some(Pattern::SmallSpike)] {
+        for pattern in Pattern::ALL {
+            assert_eq!(transitions.probability(prev, pattern), pattern.prior(prev));
+        }
+    }
+    for (pattern, prob) in transitions.stationary() {
+        assert!((prob - pattern.prior(None)).abs() < 0.02);
+        let next: f64 = Pattern::ALL.iter()
+            .map(|prev| transitions.stationary()[prev.index()].1 * transitions.probability(Some(*prev), pattern))
+            .sum();
+        assert!((prob - next).abs() < 1e-9);
+    }
+
+    // A house rule where the pattern always cycles through in order.
+    let cycle = TransitionMatrix::new([
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+        [1.0, 0.0, 0.0, 0.0],
+    ]).unwrap();
+    for (_, prob) in cycle.stationary() {
+        assert!((prob - 0.25).abs() < 1e-9);
+    }
+    assert_eq!(cycle.probability(None, Pattern::Random), 0.25);
+    let options = Options { transitions: cycle, ..Options::default() };
+    let results = run(Some(Pattern::SmallSpike), Some(100), Vec::new(), &options, false).unwrap();
+    assert_only!(results, LargeSpike);
+
+    // The unknown row can be given separately.
+    let cycle = cycle.with_unknown([0.0, 0.0, 0.0, 1.0]).unwrap();
+    let results = run(None, Some(100), Vec::new(), &Options { transitions: cycle, ..Options::default() }, false).unwrap();
+    assert_only!(results, LargeSpike);
+
+    // Rows that only add up to 1 after rounding still give a distribution,
+    // even after many weeks.
+    let rounded = TransitionMatrix::new([[0.3333333, 0.3333333, 0.3333333, 0.0]; 4]).unwrap();
+    let total: f64 = rounded.stationary().iter().map(|(_, prob)| prob).sum();
+    assert!((total - 1.0).abs() < 1e-12);
+    let options = Options { transitions: rounded, ..Options::default() };
+    assert!(Calculator::new(None, Some(100), options).is_ok());
+    let mut week: PatternDistribution = Some(Pattern::Decreasing).into();
+    for _ in 0..1000 {
+        week = rounded.next_week(&week);
+    }
+    assert!((week.known() - 1.0).abs() < 1e-12);
+
+    // Every row must be a distribution.
+    assert!(matches!(TransitionMatrix::new([[0.5; 4]; 4]), Err(CalcError::InvalidDistribution(_))));
+    assert!(matches!(TransitionMatrix::new([[-1.0, 1.0, 0.5, 0.5]; 4]), Err(CalcError::InvalidDistribution(_))));
+    assert!(matches!(cycle.with_unknown([0.0; 4]), Err(CalcError::InvalidDistribution(_))));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_transitions_json() {
+    let cycle = TransitionMatrix::new([
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+        [1.0, 0.0, 0.0, 0.0],
+    ]).unwrap();
+    let json = serde_json::to_string(&cycle).unwrap();
+    assert_eq!(serde_json::from_str::<TransitionMatrix>(&json).unwrap(), cycle);
+
+    // Matrices that aren't distributions are rejected, rather than failing later.
+    let json = r#"{"rows":[[0.5,0.5,0.5,0.5],[0.0,1.0,0.0,0.0],[0.0,0.0,1.0,0.0],[0.0,0.0,0.0,1.0]],"unknown":[0.25,0.25,0.25,0.25]}"#;
+    assert!(serde_json::from_str::<TransitionMatrix>(json).is_err());
+    let json = r#"{"rows":[[1.0,0.0,0.0,0.0],[0.0,1.0,0.0,0.0],[0.0,0.0,1.0,0.0],[0.0,0.0,0.0,1.0]],"unknown":[0.0,0.0,0.0,0.0]}"#;
+    assert!(serde_json::from_str::<TransitionMatrix>(json).is_err());
+    let json = r#"{"transitions":{"rows":[[2.0,-1.0,0.0,0.0],[0.0,1.0,0.0,0.0],[0.0,0.0,1.0,0.0],[0.0,0.0,0.0,1.0]],"unknown":[0.25,0.25,0.25,0.25]}}"#;
+    assert!(serde_json::from_str::<Options>(json).is_err());
+}
+
+#[test]
+fn test_next_week() {
+    let transitions = TransitionMatrix::default();
+
+    // A known pattern gives its row of the table.
+    let prices = vec![90, 87, 82, 78, 74, 69, 66, 61];
+    let results = next_week(None, Some(100), map_exact!(prices), &Options::default()).unwrap();
+    for pattern in Pattern::ALL {
+        assert_eq!(results.probability(pattern), transitions.probability(Some(Pattern::Decreasing), pattern));
+    }
+
+    // Otherwise, the rows are mixed according to this week's chances.
+    let prices = vec![90, 86];
+    let calculator = Calculator::with_prices(Some(Pattern::Random), Some(104), map_exact!(prices),
+                                             Options::default()).unwrap();
+    let analysis = calculator.analysis().unwrap();
+    let results = calculator.next_week().unwrap();
+    for pattern in Pattern::ALL {
+        let expected: f64 = analysis.probabilities().iter()
+            .map(|(prev, chance)| chance * transitions.probability(Some(*prev), pattern))
+            .sum();
+        assert!((results.probability(pattern) - expected).abs() < 1e-9);
+    }
+    assert!((results.known() - 1.0).abs() < 1e-9);
+    assert_debug_snapshot!(results.ranked());
+
+    // With no prices, this week is only known from last week.
+    let results = next_week(Some(Pattern::LargeSpike), Some(100), Vec::new(), &Options::default()).unwrap();
+    let expected = transitions.next_week(&transitions.next_week(&Some(Pattern::LargeSpike).into()));
+    for pattern in Pattern::ALL {
+        assert!((results.probability(pattern) - expected.probability(pattern)).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_history() {
+    // Assert that two distributions are the same, allowing for rounding.
+    macro_rules! assert_close {
+        ($a:expr, $b:expr) => {{
+            for pattern in Pattern::ALL {
+                assert!(($a.probability(pattern) - $b.probability(pattern)).abs() < 1e-9);
+            }
+        }}
+    }
+
+    let transitions = TransitionMatrix::default();
+    let decreasing = Week { base_price: Some(100), observations: map_exact!(vec![90, 87, 82, 78, 74, 69, 66, 61]) };
+    let partial = Week { base_price: Some(104), observations: map_exact!(vec![90, 86]) };
+    let unknown = Week::default();
+
+    // A single week is the same as analysing it on its own.
+    let results = history(std::slice::from_ref(&partial), Some(Pattern::Random), &Options::default()).unwrap();
+    let calculator = Calculator::with_prices(Some(Pattern::Random), Some(104), partial.observations.clone(),
+                                             Options::default()).unwrap();
+    assert_close!(results[0], PatternDistribution::from(&calculator.analysis().unwrap()));
+
+    // The latest week is the same as using the earlier weeks as last week.
+    let results = history(&[partial.clone(), decreasing.clone()], Some(Pattern::Random), &Options::default()).unwrap();
+    let mut latest = Calculator::new(&calculator.analysis().unwrap(), Some(100), Options::default()).unwrap();
+    latest.extend(decreasing.observations.clone()).unwrap();
+    assert_close!(results[1], PatternDistribution::from(&latest.analysis().unwrap()));
+    assert_eq!(results[1].probability(Pattern::Decreasing), 1.0);
+
+    // A later week tells us about an earlier one that wasn't seen at all.
+    let results = history(&[unknown.clone(), decreasing.clone()], PatternDistribution::default(),
+                          &Options::default()).unwrap();
+    let total: f64 = Pattern::ALL.iter()
+        .map(|prev| prev.prior(None) * transitions.probability(Some(*prev), Pattern::Decreasing))
+        .sum();
+    for pattern in Pattern::ALL {
+        let expected = pattern.prior(None) * transitions.probability(Some(pattern), Pattern::Decreasing) / total;
+        assert!((results[0].probability(pattern) - expected).abs() < 1e-9);
+    }
+
+    // And sharpens an earlier one that was only partly seen.
+    let results = history(&[partial, unknown, decreasing.clone()], PatternDistribution::default(),
+                          &Options::default()).unwrap();
+    assert_debug_snapshot!(results);
+    assert!(history(&[], PatternDistribution::default(), &Options::default()).unwrap().is_empty());
+
+    // Weeks that can't follow on from each other are inconsistent.
+    let cycle = TransitionMatrix::new([
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+        [1.0, 0.0, 0.0, 0.0],
+    ]).unwrap();
+    let options = Options { transitions: cycle, ..Options::default() };
+    assert_eq!(history(&[decreasing.clone(), decreasing], PatternDistribution::default(), &options), Err(CalcError::NoMatchingPattern));
+}
+
+#[test]
+fn test_fit_transitions() {
+    let week = |base_price, prices: Vec<u32>| Week { base_price: Some(base_price), observations: map_exact!(prices) };
+    let d = week(100, vec![90, 87, 82, 78, 74, 69, 66, 61, 58, 54, 50, 47]);
+    let r = week(95, vec![102, 127, 112, 112, 97, 65, 59, 96, 121, 57, 53, 43]);
+    let s = week(90, vec![55, 52, 48, 43, 38, 90, 89, 135, 170, 165, 81, 77]);
+    let l = week(104, vec![90, 86, 128, 165, 455, 147, 143, 57, 53, 43, 94, 42]);
+
+    // With every pattern known, the estimate is just how often each transition happened.
+    let histories = vec![
+        vec![d.clone(), r.clone(), s.clone(), l.clone(), d.clone()],
+        vec![d.clone(), l.clone(), r.clone()],
+    ];
+    let fit = fit_transitions(&histories, &Options::default()).unwrap();
+    let probability = |from, to| fit.transitions.probability(Some(from), to);
+    assert!((probability(Pattern::Decreasing, Pattern::Random) - 0.5).abs() < 1e-9);
+    assert!((probability(Pattern::Decreasing, Pattern::LargeSpike) - 0.5).abs() < 1e-9);
+    assert!((probability(Pattern::Random, Pattern::SmallSpike) - 1.0).abs() < 1e-9);
+    assert!((probability(Pattern::LargeSpike, Pattern::Decreasing) - 0.5).abs() < 1e-9);
+    assert!((probability(Pattern::LargeSpike, Pattern::Random) - 0.5).abs() < 1e-9);
+    assert_eq!(fit.estimates.len(), 16);
+    let estimate = &fit.estimates[Pattern::Decreasing.index() * 4 + Pattern::LargeSpike.index()];
+    assert_eq!((estimate.from, estimate.to), (Pattern::Decreasing, Pattern::LargeSpike));
+    assert!((estimate.weeks - 2.0).abs() < 1e-9);
+    assert!(estimate.min > 0.0 && estimate.min < 0.5 && estimate.max > 0.5 && estimate.max < 1.0);
+    assert_eq!(estimate.datamined, 0.45);
+    assert!(estimate.agrees());
+
+    // A transition seen only once is too little to disagree with the table,
+    // even though it happened every time.
+    let estimate = &fit.estimates[Pattern::Random.index() * 4 + Pattern::SmallSpike.index()];
+    assert!((estimate.probability - 1.0).abs() < 1e-9);
+    assert!(estimate.min < 0.35 && estimate.max == 1.0);
+    assert!(estimate.agrees());
+    for estimate in fit.estimates.iter().filter(|estimate| estimate.from == Pattern::Random) {
+        assert!(estimate.agrees());
+    }
+
+    // But the same transition every time for several weeks is.
+    let histories = vec![vec![d.clone(); 6]];
+    let fit = fit_transitions(&histories, &Options::default()).unwrap();
+    let estimate = &fit.estimates[Pattern::Decreasing.index() * 4 + Pattern::Decreasing.index()];
+    assert!((estimate.weeks - 5.0).abs() < 1e-9);
+    assert!(estimate.min > 0.5 && !estimate.agrees());
+
+    // Missing weeks are filled in with what they probably were.
+    let histories = vec![
+        vec![d.clone(), Week::default(), s.clone(), Week { base_price: None, observations: map_exact!(vec![90, 86]) }],
+        vec![l, r, Week::default(), d],
+    ];
+    let fit = fit_transitions(&histories, &Options::default()).unwrap();
+    assert!(fit.iterations > 1);
+    let weeks: f64 = fit.estimates.iter().step_by(4).map(|estimate| estimate.weeks).sum();
+    assert!((weeks - 6.0).abs() < 1e-9);
+    for from in Pattern::ALL {
+        let total: f64 = Pattern::ALL.iter().map(|to| fit.transitions.probability(Some(from), *to)).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+    }
+
+    // With no weeks at all, nothing changes.
+    let fit = fit_transitions(&[], &Options::default()).unwrap();
+    for estimate in fit.estimates {
+        assert_eq!(estimate.probability, estimate.datamined);
+        assert_eq!((estimate.min, estimate.max), (0.0, 1.0));
+    }
+}
+
+#[test]
+fn test_first_week() {
+    let options = Options { first_week: true, ..Options::default() };
+    let results = run(None, Some(90), Vec::new(), &options, false).unwrap();
+    assert_only!(results, SmallSpike);
+
+    // Prices that only fit other patterns can't happen.
+    let prices = vec![90, 87, 82, 78, 74, 69, 66, 61];
+    assert_eq!(run(None, Some(100), map_exact!(prices.clone()), &options, false),
+               Err(CalcError::NoMatchingPattern));
+
+    // Unless a price might be wrong.
+    let options = Options { error_rate: 0.01, ..options };
+    let results = run(None, Some(100), map_exact!(prices), &options, false).unwrap();
+    assert_only!(results, SmallSpike);
+
+    // There was no week before the first.
+    assert!(matches!(Calculator::new(Some(Pattern::Random), Some(100), options.clone()),
+                     Err(CalcError::InvalidOptions(_))));
+
+    // In a history, it's the first week that is the first on the island.
+    let weeks = vec![Week::default(), Week::default()];
+    let results = history(&weeks, PatternDistribution::default(), &options).unwrap();
+    assert_eq!(results[0].probability(Pattern::SmallSpike), 1.0);
+    for pattern in Pattern::ALL {
+        assert!((results[1].probability(pattern) - pattern.prior(Some(Pattern::SmallSpike))).abs() < 1e-9);
+    }
+}
+
 #[test]
 fn test_invalid() {
-    // Run the test and ensure there were no matching patterns.
+    // Run the test and ensure the given error was returned.
     macro_rules! test {
-        ($base_price:expr, $($prices:expr),*) => {{
+        ($error:expr, $base_price:expr, $($prices:expr),*) => {{
             let prices = vec![$($prices),*];
-            let results = run(None, $base_price, map_some!(prices), true);
-            assert!(results.is_empty());
+            let results = run(None, Some($base_price), map_exact!(prices), &Options::default(), true);
+            assert_eq!(results, Err($error));
         }}
     }
 
     // Weird zeroes.
-    test!(100, 0);
-    test!(0, 100);
-    test!(0, 0);
+    test!(CalcError::NoMatchingPattern, 100, 0);
+    test!(CalcError::InvalidBasePrice(0), 0, 100);
+    test!(CalcError::InvalidBasePrice(0), 0, 0);
 
     // Bad patterns.
-    test!(100, 200);
-    test!(100, 130, 126, 122, 118, 114, 110, 106, 102);
+    test!(CalcError::NoMatchingPattern, 100, 200);
+    test!(CalcError::NoMatchingPattern, 100, 130, 126, 122, 118, 114, 110, 106, 102);
+
+    // Too many prices.
+    test!(CalcError::TooManyPrices(13), 100,
+          90, 87, 82, 78, 74, 69, 66, 61, 58, 54, 50, 47, 44);
 }
 
 #[test]
 fn test_missing_prices() {
     let base_price = 90;
-    let prices = vec![None, None, Some(48), Some(43)];
-    let results = run(None, base_price, prices, true);
+    let prices = vec![None, None, Some(48), Some(43)].into_iter().map(Observation::from).collect();
+    let results = run(None, Some(base_price), prices, &Options::default(), true).unwrap();
+    assert_debug_snapshot!(results);
+}
+
+#[test]
+fn test_forecast_decreasing() {
+    let base_price = 100;
+    let prices = vec![
+        90, 87, 82, 78,
+        74, 69, 66, 61];
+    let results = forecast(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+    assert_eq!(results.len(), 4);
+    let mut prev_max = 61;
+    for (i, result) in results.iter().enumerate() {
+        assert_eq!(result.half_day, i + 8);
+        assert_eq!(result.patterns.len(), 1);
+        assert_eq!(result.patterns[0], (Pattern::Decreasing, result.overall));
+        assert!(result.overall.max < prev_max);
+        prev_max = result.overall.max;
+    }
+}
+
+#[test]
+fn test_forecast_large_spike_partial() {
+    let base_price = 104;
+    let prices = vec![90, 86];
+    let results = forecast(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+    assert_debug_snapshot!(results);
+}
+
+#[test]
+fn test_forecast_invalid() {
+    let results = forecast(None, Some(100), map_exact!(vec![200]), &Options::default());
+    assert_eq!(results.unwrap_err(), CalcError::NoMatchingPattern);
+}
+
+#[test]
+fn test_distribution_sums_to_one() {
+    let base_price = 104;
+    let prices = vec![Some(90), None, Some(82)].into_iter().map(Observation::from).collect();
+    let results = distribution(None, Some(base_price), prices, &Options::default()).unwrap();
+    assert_eq!(results.len(), 12);
+    for (i, result) in results.iter().enumerate() {
+        assert_eq!(result.half_day, i);
+        let total: f64 = result.probabilities.iter().map(|(_, p)| p).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+    }
+    assert_eq!(results[0].probabilities, vec![(90, 1.0)]);
+    assert_eq!(results[2].median(), 82);
+
+    // The missing price must lie between its neighbours.
+    let (min, _) = results[1].probabilities.first().unwrap();
+    let (max, _) = results[1].probabilities.last().unwrap();
+    assert!(*min >= 82 && *max <= 90);
+}
+
+#[test]
+fn test_distribution_missing_decreasing() {
+    // A missed price in a decreasing phase must fit the prices after it as well
+    // as the ones before: only a rate that gives 85 can go on to give 80 and 77.
+    let prices: Vec<Observation> = vec![Some(90), None, Some(80), Some(77)].into_iter().map(Observation::from).collect();
+    let options = Options { transitions: TransitionMatrix::default().with_unknown([1.0, 0.0, 0.0, 0.0]).unwrap(),
+                            float32: true, ..Options::default() };
+    let results = distribution(None, Some(100), prices.clone(), &options).unwrap();
+    assert_eq!(results[1].probabilities, vec![(85, 1.0)]);
+
+    // Without the game's arithmetic, only the tolerance for its rounding is left.
+    let results = distribution(None, Some(100), prices, &Options { float32: false, ..options }).unwrap();
+    assert!(results[1].probability(85) > 0.9999);
+}
+
+#[test]
+fn test_distribution_decreasing() {
+    let base_price = 100;
+    let prices = vec![
+        90, 87, 82, 78,
+        74, 69, 66, 61];
+    let results = distribution(None, Some(base_price), map_exact!(prices.clone()), &Options::default()).unwrap();
+    let bands = forecast(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+    for (result, band) in results[8..].iter().zip(bands) {
+        assert!(result.median() >= band.overall.min && result.median() <= band.overall.max);
+        assert!(result.expected() < 61.0);
+    }
+}
+
+#[test]
+fn test_max_price_decreasing() {
+    let base_price = 100;
+    let prices = vec![
+        90, 87, 82, 78,
+        74, 69, 66, 61];
+    let results = max_price(None, Some(base_price), map_exact!(prices.clone()), &Options::default()).unwrap();
+    let next = &distribution(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap()[8];
+
+    // The first remaining price is always the highest.
+    assert_eq!(results.probabilities.len(), next.probabilities.len());
+    for ((p1, prob1), (p2, prob2)) in results.probabilities.iter().zip(next.probabilities.iter()) {
+        assert_eq!(p1, p2);
+        assert!((prob1 - prob2).abs() < 1e-9);
+    }
+    assert_eq!(results.at_least(61), 0.0);
+}
+
+#[test]
+fn test_max_price_large_spike_partial() {
+    let base_price = 104;
+    let prices = vec![90, 86];
+    let results = max_price(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+    assert!((results.at_least(0) - 1.0).abs() < 1e-9);
+    assert!(results.at_least(base_price * 2) > 0.5);
+    assert!(results.at_least(base_price * 2) < 0.6);
+    assert!(results.expected() > base_price as f64);
+}
+
+#[test]
+fn test_max_price_full_week() {
+    let base_price = 100;
+    let prices = vec![
+        90, 87, 82, 78,
+        74, 69, 66, 61,
+        58, 54, 50, 47];
+    let results = max_price(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+    assert!(results.probabilities.is_empty());
+}
+
+#[test]
+fn test_spike_timing_full() {
+    let results = spike_timing(None, Some(104), map_exact!(vec![
+        90, 86,
+        128, 165, 455,
+        147, 143,
+        57, 53, 43, 94, 42]), &Options::default()).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].pattern, Pattern::LargeSpike);
+    assert_eq!(results[0].start[2], 1.0);
+    assert_eq!(results[0].likeliest_peak(), 4);
+
+    let results = spike_timing(None, Some(90), map_exact!(vec![
+        55, 52, 48, 43, 38,
+        90, 89, 135, 170, 165,
+        81, 77]), &Options::default()).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].pattern, Pattern::SmallSpike);
+    assert_eq!(results[0].start[5], 1.0);
+    assert_eq!(results[0].likeliest_peak(), 8);
+}
+
+#[test]
+fn test_spike_timing_partial() {
+    let base_price = 104;
+    let prices = vec![90, 86];
+    let results = spike_timing(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
     assert_debug_snapshot!(results);
 }
+
+#[test]
+fn test_current_phase() {
+    let results = current_phase(None, Some(90), map_exact!(vec![
+        55, 52, 48, 43, 38,
+        90, 89, 135, 170]), &Options::default()).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].pattern, Pattern::SmallSpike);
+    assert_eq!(results[0].phase, "Spike");
+    assert_eq!(results[0].position, 4);
+    assert_eq!(results[0].probability, 1.0);
+
+    let results = current_phase(None, Some(90), Vec::new(), &Options::default()).unwrap();
+    assert!(results.is_empty());
+}
+
+#[test]
+fn test_current_phase_partial() {
+    let base_price = 95;
+    let prices = vec![102, 127];
+    let results = current_phase(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+    assert_debug_snapshot!(results);
+}
+
+#[test]
+fn test_analysis() {
+    let base_price = 104;
+    let prices = vec![90, 86];
+    let results = run(None, Some(base_price), map_exact!(prices), &Options::default(), true).unwrap();
+
+    // Every pattern is always reported, in a stable order.
+    let patterns: Vec<Pattern> = results.probabilities().iter().map(|(p, _)| *p).collect();
+    assert_eq!(patterns, Pattern::ALL);
+    assert_eq!(results.probability(Pattern::Random), 0.0);
+    assert_eq!(results.likeliest(), Pattern::LargeSpike);
+    assert_eq!(results.ranked()[3], (Pattern::Random, 0.0));
+    assert!(results.evidence() > 0.0 && results.evidence() < 1.0);
+    assert!(results.paths() > 0);
+
+    // Seeing more prices can only make them less likely as a whole.
+    let more = run(None, Some(base_price), map_exact!(vec![90, 86, 82]), &Options::default(), true).unwrap();
+    assert!(more.evidence() < results.evidence());
+
+    // A full week of trusted prices leaves a single path, but with an error
+    // rate, every path that takes different prices as mistyped is counted.
+    let prices = vec![
+        90, 87, 82, 78,
+        74, 69, 66, 61,
+        58, 54, 50, 47];
+    let exact = run(None, Some(100), map_exact!(prices.clone()), &Options::default(), true).unwrap();
+    assert_eq!(exact.paths(), 1);
+    let noisy = run(None, Some(100), map_exact!(prices), &Options { error_rate: 0.01, ..Options::default() }, false)
+        .unwrap();
+    assert!(noisy.paths() > 1);
+}
+
+#[test]
+fn test_explain() {
+    let base_price = 100;
+    let prices = vec![
+        90, 87, 82, 78,
+        74, 69, 66, 61];
+    let results = explain(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+    assert_eq!(results[0].to_string(),
+               "Random excluded at Mon PM: 87 falls between allowed ranges 60-80 and 90-140");
+    assert_eq!(results[1].to_string(), "SmallSpike excluded at Thu PM: 61 is below min 90");
+    assert_debug_snapshot!(results);
+
+    // Everything can be excluded.
+    let results = explain(None, Some(base_price), map_exact!(vec![200]), &Options::default()).unwrap();
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[0].to_string(), "Decreasing excluded at Mon AM: 200 exceeds max 90");
+}
+
+#[test]
+fn test_diagnose() {
+    let base_price = 100;
+
+    // A typo in the middle of a Decreasing week.
+    let prices = vec![
+        90, 87, 82, 78,
+        74, 96, 66, 61];
+    let results = diagnose(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+    assert_eq!(results[0].half_days, vec![5]);
+    assert_eq!(results[0].analysis.likeliest(), Pattern::Decreasing);
+
+    // Valid prices are not suspicious.
+    let prices = vec![90, 87, 82];
+    let results = diagnose(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+    assert!(results.is_empty());
+
+    // Two typos need two prices ignoring.
+    let prices = vec![
+        90, 87, 82, 78,
+        74, 96, 66, 99,
+        58, 54, 50, 47];
+    let results = diagnose(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+    assert!(results.iter().all(|suspect| suspect.half_days.len() == 2));
+    assert_eq!(results[0].half_days, vec![5, 7]);
+}
+
+#[test]
+fn test_hidden_parameters() {
+    let base_price = 90;
+    let prices = vec![55, 52, 48, 43, 38, 90, 89, 135, 170, 165, 81, 77];
+    let results = hidden_parameters(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+    assert_eq!(results.len(), 1);
+    let small_spike = &results[0];
+    assert_eq!(small_spike.pattern, Pattern::SmallSpike);
+    let lengths: Vec<_> = small_spike.phases.iter().map(|phase| (phase.phase.as_str(), phase.lengths.clone())).collect();
+    assert_eq!(lengths, vec![
+        ("Initial Decreasing", vec![(5, 1.0)]),
+        ("Spike", vec![(5, 1.0)]),
+        ("Final Decreasing", vec![(2, 1.0)])]);
+
+    // The first price of each decreasing phase pins down the rate it started at.
+    let initial = small_spike.phases[0].initial_rate.as_ref().unwrap();
+    assert!(initial.min() * base_price as f64 > 54.0 && initial.max() * base_price as f64 <= 55.00001);
+    assert!(small_spike.phases[1].initial_rate.is_none());
+    let last = small_spike.phases[2].initial_rate.as_ref().unwrap();
+    assert!(last.min() * base_price as f64 > 80.0 && last.max() * base_price as f64 <= 81.00001);
+
+    // The peak is the price of 170 rather than one of its neighbours.
+    let peak = small_spike.peak_rate.as_ref().unwrap();
+    assert!(peak.min() * base_price as f64 > 169.0 && peak.max() * base_price as f64 <= 170.00001);
+
+    // Later prices narrow down where a phase started: 75 can only follow 77
+    // with the smallest decrease, from the top of its range.
+    let decreasing_start = |prices: Vec<u32>| {
+        let results = hidden_parameters(None, Some(base_price), map_exact!(prices), &Options::default()).unwrap();
+        let decreasing = results.into_iter().find(|params| params.pattern == Pattern::Decreasing).unwrap();
+        assert!(decreasing.peak_rate.is_none());
+        return decreasing.phases[0].initial_rate.clone().unwrap();
+    };
+    let first_only = decreasing_start(vec![77]);
+    let both = decreasing_start(vec![77, 75]);
+    assert!(both.min() > first_only.min());
+    assert_eq!(both.max(), first_only.max());
+    // Within that range, rates nearer the top are likelier still, since they
+    // leave more room for the decrease.
+    assert!(both.expected() > (both.min() + both.max()) / 2.0);
+}
+
+#[test]
+fn test_error_rate() {
+    let base_price = 100;
+    let options = Options { error_rate: 0.01, ..Options::default() };
+
+    // A typo no longer rules everything out.
+    let prices = vec![
+        90, 87, 82, 78,
+        74, 96, 66, 61];
+    assert_eq!(run(None, Some(base_price), map_exact!(prices.clone()), &Options::default(), true),
+               Err(CalcError::NoMatchingPattern));
+    let results = run(None, Some(base_price), map_exact!(prices), &options, true).unwrap();
+    assert_eq!(results.likeliest(), Pattern::Decreasing);
+    assert!(results.probability(Pattern::Decreasing) > 0.9);
+
+    // Correct prices give much the same answer as before.
+    let prices = vec![90, 87, 82, 78];
+    let exact = run(None, Some(base_price), map_exact!(prices.clone()), &Options::default(), true).unwrap();
+    let noisy = run(None, Some(base_price), map_exact!(prices), &options, true).unwrap();
+    for (pattern, prob) in exact.probabilities() {
+        assert!((noisy.probability(pattern) - prob).abs() < 0.05);
+    }
+
+    // Even a price far beyond what the game produces, e.g. with an extra digit,
+    // is just taken as a typo.
+    let exact = run(None, Some(base_price), map_exact!(vec![90]), &options, true).unwrap();
+    for typo in [Observation::Exact(1000), Observation::Exact(6001), Observation::AtLeast(700), Observation::Exact(0)] {
+        let results = run(None, Some(base_price), vec![Observation::Exact(90), typo], &options, true).unwrap();
+        for (pattern, prob) in exact.probabilities() {
+            assert!((results.probability(pattern) - prob).abs() < 1e-9);
+        }
+    }
+
+    // Most typos are a slip of a single key, so a price that is one slip away
+    // from a likely one is a likelier typo than a price that isn't: 58 could
+    // be 85 with its digits swapped, while 52 is no slip away from 85-87.
+    let decreasing = Options { transitions: TransitionMatrix::default().with_unknown([1.0, 0.0, 0.0, 0.0]).unwrap(),
+                               ..options.clone() };
+    let evidence = |typo| run(None, Some(base_price), map_exact!(vec![90, typo]), &decreasing, true).unwrap().evidence();
+    assert!(evidence(58) > evidence(52) * 100.0);
+
+    // A price too high to have been typed can't be a typo either.
+    let results = run(None, Some(base_price), vec![Observation::Exact(90), Observation::AtLeast(10000)],
+                      &options, true);
+    assert_eq!(results, Err(CalcError::NoMatchingPattern));
+
+    // If the chance of every path is too small to represent, nothing matches.
+    let options = Options { error_rate: 1e-300, ..Options::default() };
+    let results = run(None, Some(base_price), map_exact!(vec![1000, 1000]), &options, true);
+    assert_eq!(results, Err(CalcError::NoMatchingPattern));
+
+    // The error rate must be a probability.
+    let results = run(None, Some(base_price), Vec::new(), &Options { error_rate: 1.0, ..Options::default() }, true);
+    assert!(matches!(results, Err(CalcError::InvalidOptions(_))));
+}
+
+#[test]
+fn test_error_rate_history() {
+    // Paths that only differ in which prices were mistyped are merged, but what
+    // is known about earlier half-days still depends on which they were. So the
+    // distribution of each price, whether missed or not, must be the same as
+    // going through every combination of mistyped prices, with each taken as
+    // missed instead. Ranges are used, since a mistake in one could be any
+    // price, so its chance is the same for every path.
+    let base_price = 100;
+    let error_rate = 0.2;
+    let options = Options { error_rate, ..Options::default() };
+    let prices = vec![Observation::Between(89, 90), Observation::Unknown, Observation::Between(84, 85),
+                      Observation::Between(80, 81)];
+    let results = distribution(None, Some(base_price), prices.clone(), &options).unwrap();
+
+    let known: Vec<usize> = (0..prices.len()).filter(|i| prices[*i] != Observation::Unknown).collect();
+    let mut expected = vec![vec![0.0; 1000]; prices.len()];
+    let mut total = 0.0;
+    for mistyped in 0..1 << known.len() {
+        let mut typed = prices.clone();
+        let mut weight = 1.0;
+        for (bit, i) in known.iter().enumerate() {
+            if mistyped & 1 << bit != 0 {
+                // A mistyped range could have been any 2 of the 10000 prices that can be typed.
+                typed[*i] = Observation::Unknown;
+                weight *= error_rate * 2.0 / 10000.0;
+            } else {
+                weight *= 1.0 - error_rate;
+            }
+        }
+        let evidence = match run(None, Some(base_price), typed.clone(), &Options::default(), true) {
+            Ok(results) => results.evidence(),
+            Err(_) => continue,
+        };
+        let typed = distribution(None, Some(base_price), typed, &Options::default()).unwrap();
+        for (expected, typed) in expected.iter_mut().zip(&typed) {
+            for (price, prob) in typed.probabilities.iter() {
+                expected[*price as usize] += weight * evidence * prob;
+            }
+        }
+        total += weight * evidence;
+    }
+    for (expected, result) in expected.iter().zip(&results) {
+        for (price, prob) in expected.iter().enumerate() {
+            assert!((result.probability(price as u32) - prob / total).abs() < 1e-9);
+        }
+    }
+
+    // A price that no path takes as right is replaced by what it probably was.
+    let options = Options { transitions: TransitionMatrix::default().with_unknown([1.0, 0.0, 0.0, 0.0]).unwrap(),
+                            ..options };
+    let prices = map_exact!(vec![90, 87, 82, 78, 74, 690]);
+    let results = distribution(None, Some(base_price), prices, &options).unwrap();
+    assert_eq!(results[5].probability(690), 0.0);
+    assert!(results[5].probabilities.iter().all(|(price, _)| *price < 100));
+    assert!((69..=71).map(|price| results[5].probability(price)).sum::<f64>() > 0.99);
+    let total: f64 = results[5].probabilities.iter().map(|(_, prob)| prob).sum();
+    assert!((total - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_unknown_base_price() {
+    // Without any prices, every base price is equally likely.
+    let results = run(None, None, Vec::new(), &Options::default(), true).unwrap();
+    assert_eq!(results.base_prices().len(), 21);
+    assert!((results.base_price_probability(100) - 1.0 / 21.0).abs() < 1e-9);
+    assert!((results.probability(Pattern::Random) - 0.35).abs() < 1e-9);
+
+    // The prices narrow down both the pattern and the base price.
+    let prices = vec![
+        90, 87, 82, 78,
+        74, 69, 66, 61,
+        58, 54, 50, 47];
+    let results = run(None, None, map_exact!(prices), &Options::default(), true).unwrap();
+    assert_only!(results, Decreasing);
+    assert_debug_snapshot!(results.base_prices());
+    assert_eq!(results.base_price_probability(90), 0.0);
+
+    // An invalid base price is still rejected.
+    let results = run(None, Some(89), Vec::new(), &Options::default(), true);
+    assert_eq!(results, Err(CalcError::InvalidBasePrice(89)));
+}
+
+#[test]
+fn test_observations() {
+    let base_price = 104;
+    let options = Options::default();
+
+    // A vague price can still pin down the pattern.
+    let prices = vec![
+        Observation::Exact(90), Observation::Exact(86),
+        Observation::Exact(128), Observation::Exact(165), Observation::AtLeast(300)];
+    let results = run(None, Some(base_price), prices, &options, true).unwrap();
+    assert_only!(results, LargeSpike);
+
+    // A range narrows things down compared to knowing nothing.
+    let unknown = run(None, Some(base_price), map_exact!(vec![90, 86]), &options, true).unwrap();
+    let mut prices: Vec<Observation> = map_exact!(vec![90, 86]);
+    prices.push(Observation::Between(80, 84));
+    let range = run(None, Some(base_price), prices, &options, true).unwrap();
+    assert_gt!(range, unknown, Decreasing);
+
+    // Comparisons are made against the previous half-day.
+    let mut prices: Vec<Observation> = map_exact!(vec![90, 86]);
+    prices.push(Observation::HigherThanPrevious);
+    let results = run(None, Some(base_price), prices, &options, true).unwrap();
+    assert_eq!(results.probability(Pattern::Decreasing), 0.0);
+    let mut prices: Vec<Observation> = map_exact!(vec![90, 86]);
+    prices.push(Observation::LowerThanPrevious);
+    let results = run(None, Some(base_price), prices, &options, true).unwrap();
+    assert_eq!(results.probability(Pattern::Random), 0.0);
+
+    // The distribution of a vague price only covers the observed range.
+    let mut prices: Vec<Observation> = map_exact!(vec![90, 86]);
+    prices.push(Observation::Between(80, 84));
+    let results = distribution(None, Some(base_price), prices, &options).unwrap();
+    assert!(results[2].probabilities.iter().all(|(price, _)| (80..=84).contains(price)));
+    let total: f64 = results[2].probabilities.iter().map(|(_, p)| p).sum();
+    assert!((total - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_calculator() {
+    let base_price = 104;
+    let prices = vec![90, 86, 128, 165, 455];
+    let mut calculator = Calculator::new(None, Some(base_price), Options::default()).unwrap();
+    assert_eq!(calculator.analysis().unwrap(),
+               run(None, Some(base_price), Vec::new(), &Options::default(), false).unwrap());
+
+    // Adding prices one at a time gives the same results as all at once.
+    for (i, price) in prices.iter().enumerate() {
+        calculator.push(Observation::Exact(*price)).unwrap();
+        let expected = run(None, Some(base_price), map_exact!(prices[..=i].iter().copied()),
+                           &Options::default(), false).unwrap();
+        assert_eq!(calculator.analysis().unwrap(), expected);
+    }
+    assert_eq!(calculator.forecast().unwrap(),
+               forecast(None, Some(base_price), map_exact!(prices.clone()), &Options::default()).unwrap());
+
+    // Undoing goes back to the previous state.
+    let before = calculator.analysis().unwrap();
+    calculator.push(Observation::Exact(1000)).unwrap();
+    assert_eq!(calculator.analysis(), Err(CalcError::NoMatchingPattern));
+    assert_eq!(calculator.explain().last().unwrap().pattern, Pattern::LargeSpike);
+    assert_eq!(calculator.undo(), Some(Observation::Exact(1000)));
+    assert_eq!(calculator.analysis().unwrap(), before);
+
+    // Replacing the latest price is the same as starting again.
+    calculator.replace(Observation::Exact(400)).unwrap();
+    let mut replaced = prices.clone();
+    *replaced.last_mut().unwrap() = 400;
+    let observations: Vec<Observation> = map_exact!(replaced.clone());
+    assert_eq!(calculator.observations(), observations.as_slice());
+    assert_eq!(calculator.analysis().unwrap(),
+               run(None, Some(base_price), map_exact!(replaced.clone()), &Options::default(), false).unwrap());
+    // The rest of the week is worked out again for the new price.
+    assert_eq!(calculator.forecast().unwrap(),
+               forecast(None, Some(base_price), map_exact!(replaced), &Options::default()).unwrap());
+
+    // Replacing it with an impossible price fails and keeps the price it had.
+    let before = calculator.analysis().unwrap();
+    assert_eq!(calculator.replace(Observation::Exact(1000)), Err(CalcError::NoMatchingPattern));
+    assert_eq!(calculator.observations(), observations.as_slice());
+    assert_eq!(calculator.analysis().unwrap(), before);
+    assert_eq!(calculator.frontiers().len(), observations.len() + 1);
+
+    // An impossible price that was pushed can still be replaced.
+    calculator.push(Observation::Exact(1000)).unwrap();
+    calculator.replace(Observation::Unknown).unwrap();
+    assert_eq!(calculator.undo(), Some(Observation::Unknown));
+    assert_eq!(calculator.analysis().unwrap(), before);
+
+    // There are only 12 half-days.
+    for _ in 0..7 {
+        calculator.push(Observation::Unknown).unwrap();
+    }
+    assert_eq!(calculator.push(Observation::Unknown), Err(CalcError::TooManyPrices(13)));
+    assert_eq!(calculator.observations().len(), 12);
+}
+
+#[test]
+fn test_session() {
+    let mut calculator = Calculator::new(Some(Pattern::Random), Some(104), Options::default()).unwrap();
+    calculator.extend(vec![
+        Observation::Exact(90), Observation::Unknown, Observation::Between(80, 84)]).unwrap();
+
+    // Restoring gives back the same calculator.
+    let session = calculator.session();
+    let restored = Calculator::restore(session.clone()).unwrap();
+    assert_eq!(restored.observations(), calculator.observations());
+    assert_eq!(restored.analysis(), calculator.analysis());
+
+    // Sessions from the future can't be understood.
+    let future = Session { version: SESSION_VERSION + 1, ..session };
+    assert!(matches!(Calculator::restore(future), Err(CalcError::UnsupportedVersion(_))));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_session_json() {
+    let mut calculator = Calculator::new(None, None, Options { error_rate: 0.01, ..Options::default() }).unwrap();
+    calculator.extend(vec![
+        Observation::Exact(90), Observation::AtLeast(80), Observation::LowerThanPrevious]).unwrap();
+
+    // The format must stay stable, so that old sessions can be restored.
+    let json = serde_json::to_string_pretty(&calculator.session()).unwrap();
+    assert_snapshot!(json);
+
+    let restored = Calculator::restore(serde_json::from_str(&json).unwrap()).unwrap();
+    assert_eq!(restored.analysis(), calculator.analysis());
+    let analysis: Analysis = serde_json::from_str(
+        &serde_json::to_string(&calculator.analysis().unwrap()).unwrap()).unwrap();
+    assert_eq!(analysis, calculator.analysis().unwrap());
+
+    // Options that weren't saved take their defaults.
+    let json = r#"{"version":2,"last_week":{"probabilities":[0.0,0.0,0.0,1.0]},"week":{"base_price":100,"observations":[]},"options":{}}"#;
+    let restored = Calculator::restore(serde_json::from_str(json).unwrap()).unwrap();
+    assert_eq!(restored.options(), &Options::default());
+    assert_eq!(restored.last_week().probability(Pattern::LargeSpike), 1.0);
+
+    // Sessions from before distributions were supported give last week's pattern.
+    let json = r#"{"version":1,"prev_pattern":"Decreasing","week":{"base_price":100,"observations":[]},"options":{}}"#;
+    let session: Session = serde_json::from_str(json).unwrap();
+    assert_eq!(session.version, SESSION_VERSION);
+    let restored = Calculator::restore(session).unwrap();
+    assert_eq!(restored.last_week(), &Some(Pattern::Decreasing).into());
+    let json = r#"{"version":1,"prev_pattern":null,"week":{"base_price":100,"observations":[]},"options":{}}"#;
+    let restored = Calculator::restore(serde_json::from_str(json).unwrap()).unwrap();
+    assert_eq!(restored.last_week().known(), 0.0);
+
+    // Malformed sessions are rejected rather than giving a made-up analysis.
+    for probabilities in ["[2.0,0.0,0.0,0.0]", "[-1.0,0.0,0.0,0.0]"] {
+        let json = format!(r#"{{"version":2,"last_week":{{"probabilities":{}}},"week":{{"base_price":100,"observations":[]}},"options":{{}}}}"#,
+                           probabilities);
+        assert!(serde_json::from_str::<Session>(&json).is_err());
+    }
+    let json = r#"{"version":2,"last_week":{"probabilities":[0.0,0.0,0.0,0.0]},"week":{"base_price":5,"observations":[]},"options":{}}"#;
+    assert_eq!(Calculator::restore(serde_json::from_str(json).unwrap()).err(), Some(CalcError::InvalidBasePrice(5)));
+    let json = r#"{"version":2,"last_week":{"probabilities":[0.0,0.0,0.0,0.0]},"week":{"base_price":100,"observations":[]},"options":{"error_rate":1.5}}"#;
+    assert!(matches!(Calculator::restore(serde_json::from_str(json).unwrap()), Err(CalcError::InvalidOptions(_))));
+}
+
@@ -0,0 +1,82 @@
+use crate::calculator::Calculator;
+use crate::error::CalcError;
+use crate::node::MAX_HALF_DAYS;
+use crate::observation::Observation;
+use crate::options::Options;
+use crate::pattern::{Pattern, PatternDistribution};
+use crate::traverse;
+
+/// When the spike of a spike pattern is likely to happen.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpikeTiming {
+    /// The spike pattern this describes.
+    pub pattern: Pattern,
+    /// The probability of this pattern.
+    pub probability: f64,
+    /// For each half-day, the probability that the spike starts then, given this pattern.
+    pub start: Vec<f64>,
+    /// For each half-day, the probability that the peak price lands then, given this pattern.
+    pub peak: Vec<f64>,
+}
+
+impl SpikeTiming {
+    /// The half-day on which the peak is most likely to land.
+    pub fn likeliest_peak(&self) -> usize {
+        let mut best = 0;
+        for (half_day, prob) in self.peak.iter().enumerate() {
+            if *prob > self.peak[best] {
+                best = half_day;
+            }
+        }
+        return best;
+    }
+}
+
+/// Calculate when the spike is likely to happen for each spike pattern that is
+/// still possible given the prices.
+pub fn spike_timing(last_week: impl Into<PatternDistribution>, base_price: Option<u32>,
+                    prices: Vec<Observation>, options: &Options) -> Result<Vec<SpikeTiming>, CalcError> {
+    return traverse(last_week, base_price, prices, options, false)?.spike_timing();
+}
+
+impl Calculator {
+    /// Calculate when the spike is likely to happen for each spike pattern.
+    pub fn spike_timing(&self) -> Result<Vec<SpikeTiming>, CalcError> {
+        let nodes = self.complete()?;
+        let total: f64 = nodes.iter().map(|n| n.value().1).sum();
+
+        let mut results = Vec::new();
+        for pattern in [Pattern::SmallSpike, Pattern::LargeSpike] {
+            let peak_offset = match pattern {
+                Pattern::SmallSpike => 3,
+                _ => 2,
+            };
+
+            let mut timing = SpikeTiming {
+                pattern,
+                probability: 0.0,
+                start: vec![0.0; MAX_HALF_DAYS as usize],
+                peak: vec![0.0; MAX_HALF_DAYS as usize],
+            };
+            for node in nodes.iter().filter(|n| n.value().0 == pattern) {
+                // The spike always follows the initial decreasing phase.
+                let start = node.lengths()[0] as usize;
+                let prob = node.value().1;
+                timing.probability += prob;
+                timing.start[start] += prob;
+                timing.peak[start + peak_offset] += prob;
+            }
+
+            if timing.probability > 0.0 {
+                for prob in timing.start.iter_mut().chain(timing.peak.iter_mut()) {
+                    *prob /= timing.probability;
+                }
+                timing.probability /= total;
+                results.push(timing);
+            }
+        }
+
+        return Ok(results);
+    }
+}
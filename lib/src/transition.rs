@@ -0,0 +1,133 @@
+use crate::error::CalcError;
+use crate::pattern::{Pattern, PatternDistribution};
+
+/// The chance of each pattern given last week's pattern.
+/// The default is the table from New Horizons; other tables can be supplied
+/// to try out alternative datamined values or house rules.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RawMatrix"))]
+pub struct TransitionMatrix {
+    /// The chance of each pattern, in the order of `Pattern::ALL`, for each
+    /// of last week's patterns, also in the order of `Pattern::ALL`.
+    rows: [[f64; 4]; 4],
+    /// The chance of each pattern when last week's pattern is unknown.
+    unknown: [f64; 4],
+}
+
+/// A `TransitionMatrix` as saved, which is checked by `validate` before it can
+/// be used.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawMatrix {
+    rows: [[f64; 4]; 4],
+    unknown: [f64; 4],
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawMatrix> for TransitionMatrix {
+    type Error = CalcError;
+
+    fn try_from(raw: RawMatrix) -> Result<Self, Self::Error> {
+        let matrix = TransitionMatrix { rows: raw.rows, unknown: raw.unknown };
+        matrix.validate()?;
+        return Ok(matrix);
+    }
+}
+
+impl Default for TransitionMatrix {
+    fn default() -> Self {
+        let row = |prev| Pattern::ALL.map(|pattern| pattern.prior(prev));
+        TransitionMatrix {
+            rows: Pattern::ALL.map(|prev| row(Some(prev))),
+            unknown: row(None),
+        }
+    }
+}
+
+impl TransitionMatrix {
+    /// Create a matrix from the chance of each pattern (in the order of
+    /// `Pattern::ALL`) after each of last week's patterns (in the same order).
+    /// When last week's pattern is unknown, the stationary distribution is used.
+    pub fn new(rows: [[f64; 4]; 4]) -> Result<Self, CalcError> {
+        let mut matrix = TransitionMatrix { rows, unknown: [0.25; 4] };
+        matrix.unknown = matrix.stationary().map(|(_, prob)| prob);
+        matrix.validate()?;
+        return Ok(matrix);
+    }
+
+    /// Use the given chances (in the order of `Pattern::ALL`) when last week's
+    /// pattern is unknown, instead of the stationary distribution.
+    pub fn with_unknown(mut self, unknown: [f64; 4]) -> Result<Self, CalcError> {
+        self.unknown = unknown;
+        self.validate()?;
+        return Ok(self);
+    }
+
+    /// The chance of the given pattern occurring, given last week's pattern.
+    pub fn probability(&self, prev: Option<Pattern>, pattern: Pattern) -> f64 {
+        let row = match prev {
+            Some(prev) => &self.rows[prev.index()],
+            None => &self.unknown,
+        };
+        return row[pattern.index()];
+    }
+
+    /// The chance of each pattern occurring the week after the given one.
+    pub fn next_week(&self, last_week: &PatternDistribution) -> PatternDistribution {
+        let mut probabilities = Pattern::ALL.map(|pattern| {
+            let mut prob = (1.0 - last_week.known()) * self.probability(None, pattern);
+            for prev in Pattern::ALL {
+                prob += last_week.probability(prev) * self.probability(Some(prev), pattern);
+            }
+            (pattern, prob)
+        });
+        // Each row only adds up to 1 to within rounding, so scale the result to
+        // make sure it is still a distribution.
+        let total: f64 = probabilities.iter().map(|(_, prob)| prob).sum();
+        for (_, prob) in probabilities.iter_mut() {
+            *prob /= total;
+        }
+        return PatternDistribution::new(&probabilities)
+            .expect("BUG: Transitions gave an invalid distribution!");
+    }
+
+    /// The long-run chance of each pattern, i.e. the distribution that doesn't
+    /// change from one week to the next.
+    /// For the default matrix, the unknown row is a rounded version of this.
+    pub fn stationary(&self) -> [(Pattern, f64); 4] {
+        // Iterate the lazy chain, which has the same stationary distribution
+        // but is guaranteed to converge.
+        let mut dist = [0.25; 4];
+        for _ in 0..10_000 {
+            let mut next = [0.0; 4];
+            for (prev, row) in self.rows.iter().enumerate() {
+                for (pattern, prob) in row.iter().enumerate() {
+                    next[pattern] += 0.5 * dist[prev] * prob;
+                }
+                next[prev] += 0.5 * dist[prev];
+            }
+            let change: f64 = dist.iter().zip(next).map(|(a, b)| (a - b).abs()).sum();
+            dist = next;
+            if change < 1e-15 {
+                break;
+            }
+        }
+        // Rows that add up to slightly less than 1 leak a little each week.
+        let total: f64 = dist.iter().sum();
+        return Pattern::ALL.map(|pattern| (pattern, dist[pattern.index()] / total));
+    }
+
+    /// Check that every row is a valid distribution.
+    pub(crate) fn validate(&self) -> Result<(), CalcError> {
+        for row in self.rows.iter().chain([&self.unknown]) {
+            if row.iter().any(|prob| prob.is_nan() || *prob < 0.0) {
+                return Err(CalcError::InvalidDistribution("Probabilities must not be negative."));
+            }
+            if (row.iter().sum::<f64>() - 1.0).abs() > 1e-6 {
+                return Err(CalcError::InvalidDistribution("Each row of transitions must add up to 1."));
+            }
+        }
+        return Ok(());
+    }
+}